@@ -0,0 +1,109 @@
+use compute_file_server_metadata::{AccessRule, Algorithm, Auth};
+use openssl::hash::MessageDigest;
+use simple_error::bail;
+use std::path::Path;
+
+/// The name of the file, in the root of the uploaded folder, which requires
+/// credentials for every path under a prefix.
+///
+/// ```json
+/// [
+///   {
+///     "prefix": "/internal/",
+///     "basic": {
+///       "realm": "Internal docs",
+///       "users": { "alice": "$pbkdf2-sha256$10000$..." }
+///     }
+///   },
+///   {
+///     "prefix": "/beta/",
+///     "jwt": {
+///       "algorithm": "RS256",
+///       "key": "-----BEGIN PUBLIC KEY-----\n...",
+///       "issuer": "https://auth.example.com/",
+///       "audience": "beta",
+///       "cookie": "session"
+///     }
+///   }
+/// ]
+/// ```
+///
+/// The first rule whose prefix covers a path applies. Passwords are hashed
+/// with the `hash-password` command.
+pub const ACCESS_FILE: &str = "_access.json";
+
+/// Reads and checks the rules in the `_access.json` file in `root`,
+/// returning `None` if it does not exist.
+pub fn read(root: &Path) -> Result<Option<Vec<AccessRule>>, Box<dyn std::error::Error>> {
+    let path = root.join(ACCESS_FILE);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let rules = match serde_json::from_str::<Vec<AccessRule>>(&std::fs::read_to_string(&path)?) {
+        Ok(rules) => rules,
+        Err(e) => {
+            bail!("{} is invalid: {}", ACCESS_FILE, e);
+        }
+    };
+    for rule in &rules {
+        if !rule.prefix.starts_with('/') {
+            bail!(
+                "{} has a prefix which does not start with `/`: `{}`",
+                ACCESS_FILE,
+                rule.prefix
+            );
+        }
+        match &rule.auth {
+            Auth::Basic(basic) => {
+                for (user, hash) in &basic.users {
+                    if user.contains(':') || !hash.starts_with("$pbkdf2-sha256$") {
+                        bail!(
+                            "{} has an invalid user `{}` for `{}`, whose password must be hashed with `hash-password`",
+                            ACCESS_FILE,
+                            user,
+                            rule.prefix
+                        );
+                    }
+                }
+            }
+            Auth::Jwt(jwt) => {
+                let valid = match jwt.algorithm {
+                    Algorithm::HS256 => !jwt.key.is_empty(),
+                    Algorithm::RS256 => jwt.key.contains("-----BEGIN"),
+                };
+                if !valid {
+                    bail!(
+                        "{} has an invalid key for `{}`, which must be a secret for HS256 or a PEM encoded public key for RS256",
+                        ACCESS_FILE,
+                        rule.prefix
+                    );
+                }
+            }
+        }
+    }
+    Ok(Some(rules))
+}
+
+/// Returns the hash of `password` in the form the library verifies,
+/// `$pbkdf2-sha256$<iterations>$<salt>$<hash>`, with a random salt.
+pub fn hash_password(
+    password: &str,
+    iterations: u32,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut salt = [0; 16];
+    openssl::rand::rand_bytes(&mut salt)?;
+    let mut hash = [0; 32];
+    openssl::pkcs5::pbkdf2_hmac(
+        password.as_bytes(),
+        &salt,
+        iterations.try_into()?,
+        MessageDigest::sha256(),
+        &mut hash,
+    )?;
+    Ok(format!(
+        "$pbkdf2-sha256${}${}${}",
+        iterations,
+        base64::encode_config(salt, base64::STANDARD_NO_PAD),
+        base64::encode_config(hash, base64::STANDARD_NO_PAD)
+    ))
+}
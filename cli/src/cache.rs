@@ -0,0 +1,162 @@
+use crate::headers::matches;
+use simple_error::bail;
+
+/// The `Cache-Control` of files whose name contains a content hash, such as
+/// `app.3f9a2c1b.js`, as their contents can never change.
+pub const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+/// The `Cache-Control` of HTML documents, which must be revalidated so that
+/// a deploy is picked up by browsers straight away.
+pub const HTML: &str = "no-cache";
+
+/// Chooses the `Cache-Control` and `Surrogate-Control` stored with each
+/// file. Rules given on the command line as `PATTERN=VALUE` are tested in
+/// order and the first match wins, falling back to [`IMMUTABLE`] for
+/// fingerprinted files and [`HTML`] for HTML documents.
+#[derive(Default, Debug, Clone)]
+pub struct CachePolicy {
+    cache_control: Vec<(String, String)>,
+    surrogate_control: Vec<(String, String)>,
+}
+
+impl CachePolicy {
+    pub fn from_args(
+        matches: &clap::ArgMatches,
+    ) -> Result<CachePolicy, Box<dyn std::error::Error>> {
+        Ok(CachePolicy {
+            cache_control: parse_rules(matches, "cache-control")?,
+            surrogate_control: parse_rules(matches, "surrogate-control")?,
+        })
+    }
+
+    pub fn cache_control_for(&self, path: &str, content_type: Option<&str>) -> Option<String> {
+        if let Some(value) = first_match(&self.cache_control, path) {
+            return Some(value);
+        }
+        let file_name = path.rsplit('/').next().unwrap_or(path);
+        if is_fingerprinted(file_name) {
+            return Some(IMMUTABLE.to_string());
+        }
+        if content_type.is_some_and(|content_type| content_type.starts_with("text/html")) {
+            return Some(HTML.to_string());
+        }
+        None
+    }
+
+    pub fn surrogate_control_for(&self, path: &str) -> Option<String> {
+        first_match(&self.surrogate_control, path)
+    }
+}
+
+fn parse_rules(
+    matches: &clap::ArgMatches,
+    name: &str,
+) -> Result<Vec<(String, String)>, Box<dyn std::error::Error>> {
+    let mut rules = Vec::new();
+    for rule in matches.get_many::<String>(name).into_iter().flatten() {
+        match rule.split_once('=') {
+            Some((pattern, value)) if pattern.starts_with('/') => {
+                rules.push((pattern.to_string(), value.trim().to_string()))
+            }
+            _ => {
+                bail!(
+                "--{} expects `PATTERN=VALUE`, such as `/assets/*=public, max-age=600`, but found `{}`",
+                name,
+                rule
+            );
+            }
+        }
+    }
+    Ok(rules)
+}
+
+fn first_match(rules: &[(String, String)], path: &str) -> Option<String> {
+    rules
+        .iter()
+        .find(|(pattern, _)| matches(pattern, path))
+        .map(|(_, value)| value.to_owned())
+}
+
+/// Returns whether `file_name` contains a content hash, as added by bundlers
+/// such as webpack (`app.3f9a2c1b.js`) and Vite (`index-BmJ3kZ8a.js`).
+///
+/// A hash is either a run of at least eight letters and digits between `.`,
+/// `-` or `_` separators, see [`is_hash`], or an eight character base64url
+/// hash ending the name, as Rollup and Vite add, which may itself contain
+/// `-` or `_`, such as `index-B-mJ3kZ8.js`.
+pub fn is_fingerprinted(file_name: &str) -> bool {
+    let stem = match file_name.rsplit_once('.') {
+        Some((stem, _)) => stem,
+        None => return false,
+    };
+    stem.split(['.', '-', '_']).any(is_hash) || ends_with_base64url_hash(stem)
+}
+
+/// Returns whether `token` is a run of at least eight letters and digits
+/// which mixes digits with lowercase hex letters, with upper and lowercase
+/// letters, or with lowercase letters often enough to look random, so that
+/// names like `html5shiv.js`, `ecmascript2015.js` and `report-20230101.pdf`
+/// are not mistaken for one.
+fn is_hash(token: &str) -> bool {
+    if token.len() < 8 || !token.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return false;
+    }
+    let has_digit = token.chars().any(|c| c.is_ascii_digit());
+    let is_hex = token
+        .chars()
+        .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c));
+    let has_upper = token.chars().any(|c| c.is_ascii_uppercase());
+    let has_lower = token.chars().any(|c| c.is_ascii_lowercase());
+    let alternations = token
+        .as_bytes()
+        .windows(2)
+        .filter(|pair| pair[0].is_ascii_digit() != pair[1].is_ascii_digit())
+        .count();
+    has_digit && has_lower && (is_hex || has_upper || alternations >= 3)
+}
+
+/// Returns whether `stem` ends with `-` or `.` followed by eight base64url
+/// characters which contain `-` or `_` and mix digits with upper and
+/// lowercase letters.
+fn ends_with_base64url_hash(stem: &str) -> bool {
+    let bytes = stem.as_bytes();
+    if bytes.len() < 9 || !matches!(bytes[bytes.len() - 9], b'-' | b'.') {
+        return false;
+    }
+    let hash = &bytes[bytes.len() - 8..];
+    hash.iter()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, b'-' | b'_'))
+        && hash.iter().any(|c| matches!(c, b'-' | b'_'))
+        && hash.iter().any(u8::is_ascii_digit)
+        && hash.iter().any(u8::is_ascii_uppercase)
+        && hash.iter().any(u8::is_ascii_lowercase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_fingerprinted_finds_content_hashes() {
+        for (file_name, fingerprinted) in [
+            ("app.3f9a2c1b.js", true),
+            ("main.d41d8cd98f00b204e9800998ecf8427e.css", true),
+            ("index-BmJ3kZ8a.js", true),
+            ("index-B-mJ3kZ8.js", true),
+            ("chunk-a_9Kd2Lm.css", true),
+            ("main.k3j9x2m1.js", true),
+            ("vendor-7x2kq9dm.js", true),
+            ("html5shiv.js", false),
+            ("ecmascript2015.js", false),
+            ("report-20230101.pdf", false),
+            ("jquery-3.6.0.min.js", false),
+            ("my-Component-v2.js", false),
+            ("Button-Primary.js", false),
+            ("my-Foo_Barz.js", false),
+            ("logo.png", false),
+            ("LICENSE", false),
+        ] {
+            assert_eq!(is_fingerprinted(file_name), fingerprinted, "{}", file_name);
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use regex::Regex;
+use reqwest::header::{HeaderName, HeaderValue};
+use simple_error::bail;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The name of the file, in the root of the uploaded folder, which sets
+/// response headers for paths matching a pattern.
+///
+/// ```text
+/// # Comments start with a hash
+/// /*
+///   X-Frame-Options: DENY
+/// /assets/*
+///   Cache-Control: public, max-age=31536000, immutable
+/// /blog/:slug/index.html
+///   Content-Security-Policy: default-src 'self'
+/// ```
+pub const HEADERS_FILE: &str = "_headers";
+
+#[derive(Default, Debug, Clone)]
+pub struct HeaderRules {
+    rules: Vec<HeaderRule>,
+}
+
+#[derive(Debug, Clone)]
+struct HeaderRule {
+    matcher: Matcher,
+    headers: Vec<(String, String)>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Matcher {
+    /// A pattern in the syntax of the `_headers` file, see [`matches`].
+    Pattern(String),
+    /// A regular expression matched against the whole path, used for rules
+    /// imported from other hosts' configuration files.
+    Regex(Regex),
+}
+
+impl Matcher {
+    pub fn is_match(&self, path: &str) -> bool {
+        match self {
+            Matcher::Pattern(pattern) => matches(pattern, path),
+            Matcher::Regex(regex) => regex.is_match(path),
+        }
+    }
+}
+
+impl HeaderRules {
+    /// Reads the `_headers` file from `root`, returning no rules if it does
+    /// not exist.
+    pub fn read(root: &Path) -> Result<HeaderRules, Box<dyn std::error::Error>> {
+        let path = root.join(HEADERS_FILE);
+        if !path.is_file() {
+            return Ok(HeaderRules::default());
+        }
+        HeaderRules::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(contents: &str) -> Result<HeaderRules, Box<dyn std::error::Error>> {
+        let mut rules: Vec<HeaderRule> = Vec::new();
+        for (number, line) in contents.lines().enumerate() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            if !line.starts_with(char::is_whitespace) {
+                rules.push(HeaderRule {
+                    matcher: Matcher::Pattern(trimmed.to_string()),
+                    headers: Vec::new(),
+                });
+                continue;
+            }
+            let rule = match rules.last_mut() {
+                Some(rule) => rule,
+                None => {
+                    bail!(
+                        "{} line {}: header `{}` is not preceded by a path",
+                        HEADERS_FILE,
+                        number + 1,
+                        trimmed
+                    );
+                }
+            };
+            let (name, value) = match trimmed.split_once(':') {
+                Some((name, value)) => (name.trim(), value.trim()),
+                None => {
+                    bail!(
+                        "{} line {}: expected `Name: value` but found `{}`",
+                        HEADERS_FILE,
+                        number + 1,
+                        trimmed
+                    );
+                }
+            };
+            // The library fails every request for a file whose headers it
+            // cannot send, so they are checked here instead.
+            if HeaderName::from_bytes(name.as_bytes()).is_err() {
+                bail!(
+                    "{} line {}: `{}` is not a valid header name",
+                    HEADERS_FILE,
+                    number + 1,
+                    name
+                );
+            }
+            if HeaderValue::from_str(value).is_err() {
+                bail!(
+                    "{} line {}: the value of `{}` is not a valid header value",
+                    HEADERS_FILE,
+                    number + 1,
+                    name
+                );
+            }
+            rule.headers.push((name.to_string(), value.to_string()));
+        }
+        Ok(HeaderRules { rules })
+    }
+
+    /// Adds a rule after those already read, such as one imported from
+    /// another host's configuration file.
+    pub fn push(&mut self, matcher: Matcher, headers: Vec<(String, String)>) {
+        self.rules.push(HeaderRule { matcher, headers });
+    }
+
+    /// Returns the headers of every rule matching the file at `path`, or
+    /// any URL it is served at, in file order. A header set by more than
+    /// one rule keeps each value, as headers such as `Set-Cookie` cannot be
+    /// joined into one.
+    pub fn headers_for(&self, path: &str) -> BTreeMap<String, Vec<String>> {
+        let paths = url_paths(path);
+        let mut headers: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for rule in self
+            .rules
+            .iter()
+            .filter(|rule| paths.iter().any(|path| rule.matcher.is_match(path)))
+        {
+            for (name, value) in &rule.headers {
+                headers
+                    .entry(name.to_owned())
+                    .or_default()
+                    .push(value.to_owned());
+            }
+        }
+        headers
+    }
+}
+
+/// Returns the key of a file followed by the URL paths it is served at, so
+/// `/about/index.html` is also `/about/` and `/about`, and `/page.html` is
+/// also `/page`.
+pub fn url_paths(key: &str) -> Vec<String> {
+    let mut paths = vec![key.to_string()];
+    if let Some(directory) = key.strip_suffix("/index.html") {
+        paths.push(format!("{}/", directory));
+        if !directory.is_empty() {
+            paths.push(directory.to_string());
+        }
+    } else if let Some(page) = key.strip_suffix(".html") {
+        paths.push(page.to_string());
+    }
+    paths
+}
+
+/// Returns whether `path` matches `pattern`, where `*` matches any run of
+/// characters and a `:placeholder` segment matches exactly one segment.
+pub fn matches(pattern: &str, path: &str) -> bool {
+    matches_from(pattern, path, false)
+}
+
+fn matches_from(pattern: &str, path: &str, segment_start: bool) -> bool {
+    if let Some(rest) = pattern.strip_prefix('*') {
+        return path
+            .char_indices()
+            .map(|(index, _)| index)
+            .chain(std::iter::once(path.len()))
+            .any(|index| matches_from(rest, &path[index..], false));
+    }
+    if segment_start && pattern.starts_with(':') {
+        let pattern_end = pattern.find('/').unwrap_or(pattern.len());
+        let path_end = path.find('/').unwrap_or(path.len());
+        return path_end > 0 && matches_from(&pattern[pattern_end..], &path[path_end..], false);
+    }
+    match (pattern.chars().next(), path.chars().next()) {
+        (None, None) => true,
+        (Some(p), Some(c)) if p == c => {
+            matches_from(&pattern[p.len_utf8()..], &path[c.len_utf8()..], p == '/')
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(rules: &HeaderRules, path: &str) -> Vec<String> {
+        rules.headers_for(path).into_keys().collect()
+    }
+
+    #[test]
+    fn rules_match_the_urls_a_file_is_served_at() {
+        let rules =
+            HeaderRules::parse("/\n  Root: 1\n/about\n  About: 1\n/page\n  Page: 1\n").unwrap();
+        assert_eq!(names(&rules, "/index.html"), ["Root"]);
+        assert_eq!(names(&rules, "/about/index.html"), ["About"]);
+        assert_eq!(names(&rules, "/page.html"), ["Page"]);
+        assert!(names(&rules, "/page.css").is_empty());
+    }
+
+    #[test]
+    fn a_rule_matching_several_urls_applies_once() {
+        let rules = HeaderRules::parse("/about*\n  Link: </a.css>\n").unwrap();
+        assert_eq!(rules.headers_for("/about/index.html")["Link"], ["</a.css>"]);
+    }
+
+    #[test]
+    fn headers_set_by_several_rules_keep_each_value() {
+        let rules = HeaderRules::parse(
+            "/*\n  Set-Cookie: a=1; Path=/\n/about\n  Set-Cookie: b=2, c; Path=/\n",
+        )
+        .unwrap();
+        assert_eq!(
+            rules.headers_for("/about.html")["Set-Cookie"],
+            ["a=1; Path=/", "b=2, c; Path=/"]
+        );
+        assert_eq!(
+            rules.headers_for("/index.html")["Set-Cookie"],
+            ["a=1; Path=/"]
+        );
+    }
+
+    #[test]
+    fn invalid_headers_are_rejected_with_their_line() {
+        let error = HeaderRules::parse("/*\n  X Frame: DENY\n").unwrap_err();
+        assert!(error.to_string().contains("line 2"));
+        assert!(HeaderRules::parse("/*\n  X-Test: a\u{7f}b\n").is_err());
+        assert!(HeaderRules::parse("/*\n  : value\n").is_err());
+    }
+}
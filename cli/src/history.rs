@@ -0,0 +1,75 @@
+use serde_derive::{Deserialize, Serialize};
+use std::path::Path;
+
+/// How many deployments the log keeps, dropping the oldest.
+const MAX_DEPLOYMENTS: usize = 500;
+
+/// A deployment made with `upload`.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Deployment {
+    pub id: String,
+    /// When the upload finished, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    pub files: usize,
+    pub failed: usize,
+    pub pruned: usize,
+    pub bytes: u64,
+    /// The SHA-256 digest of the files in the asset manifest, without its
+    /// deployment id, so that deployments which serve the same files have
+    /// the same digest.
+    pub manifest: String,
+    /// The branch whose canary was promoted with `canary promote`, rather
+    /// than uploaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub promoted: Option<String>,
+}
+
+/// Every deployment under a prefix, oldest first.
+pub type History = Vec<Deployment>;
+
+/// Appends `deployment` to `history`, dropping the oldest deployments once
+/// there are too many.
+pub fn append(history: &mut History, deployment: Deployment) {
+    history.push(deployment);
+    if history.len() > MAX_DEPLOYMENTS {
+        history.drain(..history.len() - MAX_DEPLOYMENTS);
+    }
+}
+
+/// Returns the output of `git` run with `args` in `path`, or `None` if it is
+/// not a repository or git is not installed.
+pub fn git(path: &Path, args: &[&str]) -> Option<String> {
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(path)
+        .args(args)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let output = String::from_utf8(output.stdout).ok()?;
+    Some(output.trim().to_string()).filter(|output| !output.is_empty())
+}
+
+/// Returns the commit and branch checked out in the repository containing
+/// `path`, where the branch is `None` when the HEAD is detached.
+pub fn git_revision(path: &Path) -> (Option<String>, Option<String>) {
+    (
+        git(path, &["rev-parse", "HEAD"]),
+        git(path, &["rev-parse", "--abbrev-ref", "HEAD"]).filter(|branch| branch != "HEAD"),
+    )
+}
+
+/// Returns who is deploying, preferring their git identity to their login.
+pub fn user(path: &Path) -> Option<String> {
+    git(path, &["config", "user.email"])
+        .or_else(|| std::env::var("USER").ok())
+        .or_else(|| std::env::var("USERNAME").ok())
+}
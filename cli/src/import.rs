@@ -0,0 +1,607 @@
+use crate::headers::Matcher;
+use compute_file_server_metadata::RedirectRule;
+use regex::Regex;
+use reqwest::header::{HeaderName, HeaderValue};
+use serde_json::{Map, Value};
+use simple_error::bail;
+use std::path::Path;
+
+/// The parts of a `vercel.json` or `firebase.json` file which the file
+/// server understands, translated into its own rules.
+#[derive(Default, Debug, Clone)]
+pub struct Imported {
+    pub headers: Vec<(Matcher, Vec<(String, String)>)>,
+    pub redirects: Vec<RedirectRule>,
+    pub trailing_slash: Option<bool>,
+    pub clean_urls: bool,
+    /// A description of each directive which has no equivalent in the file
+    /// server and so was skipped.
+    pub unsupported: Vec<String>,
+}
+
+/// Reads the hosting configuration in `path`, choosing the importer from its
+/// file name.
+pub fn read(path: &Path) -> Result<Imported, Box<dyn std::error::Error>> {
+    let config: Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    match path.file_name().and_then(|name| name.to_str()) {
+        Some("vercel.json") | Some("now.json") => vercel(&config),
+        Some("firebase.json") => firebase(&config),
+        _ => {
+            bail!(
+            "`{}` is not a supported configuration file, expected `vercel.json` or `firebase.json`",
+            path.display()
+        );
+        }
+    }
+}
+
+fn vercel(config: &Value) -> Result<Imported, Box<dyn std::error::Error>> {
+    let config = match config.as_object() {
+        Some(config) => config,
+        None => {
+            bail!("vercel.json must contain an object");
+        }
+    };
+    let mut imported = Imported::default();
+    for (key, value) in config {
+        match key.as_str() {
+            "$schema" => {}
+            "cleanUrls" => imported.clean_urls = value.as_bool().unwrap_or(false),
+            "trailingSlash" => imported.trailing_slash = value.as_bool(),
+            "redirects" => {
+                for redirect in objects(value) {
+                    let source = string(redirect, "source");
+                    if let Some(reason) = conditional(redirect) {
+                        imported
+                            .unsupported
+                            .push(format!("redirect `{}` {}", source, reason));
+                        continue;
+                    }
+                    let permanent = redirect.get("permanent").and_then(Value::as_bool);
+                    let status = match redirect.get("statusCode").and_then(Value::as_u64) {
+                        Some(status) => status as u16,
+                        None if permanent == Some(false) => 307,
+                        None => 308,
+                    };
+                    match vercel_rule(&source, &string(redirect, "destination"), status, true) {
+                        Ok(rule) => imported.redirects.push(rule),
+                        Err(reason) => imported
+                            .unsupported
+                            .push(format!("redirect `{}` {}", source, reason)),
+                    }
+                }
+            }
+            "rewrites" => {
+                for rewrite in objects(value) {
+                    let source = string(rewrite, "source");
+                    let destination = string(rewrite, "destination");
+                    if let Some(reason) = conditional(rewrite) {
+                        imported
+                            .unsupported
+                            .push(format!("rewrite `{}` {}", source, reason));
+                        continue;
+                    }
+                    if !destination.starts_with('/') {
+                        imported.unsupported.push(format!(
+                            "rewrite `{}` proxies to `{}`, only paths within the store can be served",
+                            source, destination
+                        ));
+                        continue;
+                    }
+                    match vercel_rule(&source, &destination, 200, false) {
+                        Ok(rule) => imported.redirects.push(rule),
+                        Err(reason) => imported
+                            .unsupported
+                            .push(format!("rewrite `{}` {}", source, reason)),
+                    }
+                }
+            }
+            "headers" => {
+                for rule in objects(value) {
+                    let source = string(rule, "source");
+                    if let Some(reason) = conditional(rule) {
+                        imported
+                            .unsupported
+                            .push(format!("headers for `{}` {}", source, reason));
+                        continue;
+                    }
+                    match path_to_regex(&source)
+                        .and_then(|regex| compile(&regex))
+                        .and_then(|regex| Ok((regex, headers(rule)?)))
+                    {
+                        Ok((regex, headers)) => {
+                            imported.headers.push((Matcher::Regex(regex), headers))
+                        }
+                        Err(reason) => imported
+                            .unsupported
+                            .push(format!("headers for `{}` {}", source, reason)),
+                    }
+                }
+            }
+            other => imported.unsupported.push(format!("`{}`", other)),
+        }
+    }
+    Ok(imported)
+}
+
+fn firebase(config: &Value) -> Result<Imported, Box<dyn std::error::Error>> {
+    let mut imported = Imported::default();
+    let hosting = match config.get("hosting") {
+        Some(Value::Object(hosting)) => hosting,
+        Some(Value::Array(sites)) if !sites.is_empty() => {
+            if sites.len() > 1 {
+                imported.unsupported.push(format!(
+                    "all but the first of the {} sites in `hosting`",
+                    sites.len()
+                ));
+            }
+            match sites[0].as_object() {
+                Some(hosting) => hosting,
+                None => {
+                    bail!("firebase.json `hosting` must contain objects");
+                }
+            }
+        }
+        _ => {
+            bail!("firebase.json must contain a `hosting` object");
+        }
+    };
+    for (key, value) in hosting {
+        match key.as_str() {
+            // These choose what is deployed, which is done by the arguments
+            // to this command instead.
+            "public" | "site" | "target" => {}
+            "cleanUrls" => imported.clean_urls = value.as_bool().unwrap_or(false),
+            "trailingSlash" => imported.trailing_slash = value.as_bool(),
+            "redirects" => {
+                for redirect in objects(value) {
+                    let status = redirect.get("type").and_then(Value::as_u64).unwrap_or(301) as u16;
+                    match firebase_rule(redirect, status, true) {
+                        Ok(rule) => imported.redirects.push(rule),
+                        Err(reason) => imported.unsupported.push(format!("redirect {}", reason)),
+                    }
+                }
+            }
+            "rewrites" => {
+                for rewrite in objects(value) {
+                    match firebase_rule(rewrite, 200, false) {
+                        Ok(rule) => imported.redirects.push(rule),
+                        Err(reason) => imported.unsupported.push(format!("rewrite {}", reason)),
+                    }
+                }
+            }
+            "headers" => {
+                for rule in objects(value) {
+                    let regex = if let Some(regex) = rule.get("regex").and_then(Value::as_str) {
+                        compile(&format!("^(?:{})$", regex))
+                    } else {
+                        let glob = rule
+                            .get("source")
+                            .or_else(|| rule.get("glob"))
+                            .and_then(Value::as_str)
+                            .unwrap_or("");
+                        glob_to_regex(glob).and_then(|regex| compile(&regex))
+                    };
+                    match regex.and_then(|regex| Ok((regex, headers(rule)?))) {
+                        Ok((regex, headers)) => {
+                            imported.headers.push((Matcher::Regex(regex), headers))
+                        }
+                        Err(reason) => imported.unsupported.push(format!("headers {}", reason)),
+                    }
+                }
+            }
+            other => imported.unsupported.push(format!("`hosting.{}`", other)),
+        }
+    }
+    Ok(imported)
+}
+
+fn objects(value: &Value) -> impl Iterator<Item = &Map<String, Value>> {
+    value
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_object)
+}
+
+fn string(object: &Map<String, Value>, key: &str) -> String {
+    object
+        .get(key)
+        .and_then(Value::as_str)
+        .unwrap_or("")
+        .to_string()
+}
+
+/// Returns the headers of a rule, or why one of them cannot be sent.
+fn headers(rule: &Map<String, Value>) -> Result<Vec<(String, String)>, String> {
+    objects(rule.get("headers").unwrap_or(&Value::Null))
+        .map(|header| {
+            let (name, value) = (string(header, "key"), string(header, "value"));
+            if HeaderName::from_bytes(name.as_bytes()).is_err() {
+                return Err(format!("has an invalid header name `{}`", name));
+            }
+            if HeaderValue::from_str(&value).is_err() {
+                return Err(format!("has an invalid value for `{}`", name));
+            }
+            Ok((name, value))
+        })
+        .collect()
+}
+
+/// Returns why a Vercel rule with `has` or `missing` conditions cannot be
+/// imported.
+fn conditional(rule: &Map<String, Value>) -> Option<&'static str> {
+    if rule.contains_key("has") || rule.contains_key("missing") {
+        Some("uses `has` or `missing` conditions")
+    } else {
+        None
+    }
+}
+
+fn compile(regex: &str) -> Result<Regex, String> {
+    Regex::new(regex).map_err(|error| format!("is not a valid pattern: {}", error))
+}
+
+fn vercel_rule(
+    source: &str,
+    destination: &str,
+    status: u16,
+    force: bool,
+) -> Result<RedirectRule, String> {
+    let mut from = Vec::new();
+    let mut splat = None;
+    let segments = source.split('/').collect::<Vec<&str>>();
+    for (index, segment) in segments.iter().enumerate() {
+        let last = index == segments.len() - 1;
+        let name = segment.strip_prefix(':');
+        if let Some(name) = name.filter(|name| is_name(name)) {
+            from.push(format!(":{}", name));
+        } else if let (true, Some(name)) = (
+            last,
+            name.and_then(|name| name.strip_suffix('*').or_else(|| name.strip_suffix("(.*)"))),
+        ) {
+            from.push("*".to_string());
+            splat = Some(format!(":{}", name));
+        } else if last && *segment == "(.*)" {
+            from.push("*".to_string());
+            splat = Some("$1".to_string());
+        } else if segment.contains([':', '(', ')', '*', '+', '?']) {
+            return Err(format!(
+                "uses the pattern `{}` which cannot be translated",
+                segment
+            ));
+        } else {
+            from.push(segment.to_string());
+        }
+    }
+    let mut to = destination.to_string();
+    if let Some(splat) = splat {
+        for suffix in ["*", "+", ""] {
+            to = to.replace(&format!("{}{}", splat, suffix), ":splat");
+        }
+    }
+    Ok(RedirectRule {
+        from: from.join("/"),
+        to,
+        status,
+        force,
+    })
+}
+
+fn firebase_rule(
+    rule: &Map<String, Value>,
+    status: u16,
+    force: bool,
+) -> Result<RedirectRule, String> {
+    if let Some(regex) = rule.get("regex").and_then(Value::as_str) {
+        return Err(format!("`{}` uses a regular expression", regex));
+    }
+    let source = string(rule, "source");
+    let destination = string(rule, "destination");
+    for target in ["function", "run", "dynamicLinks"] {
+        if rule.contains_key(target) {
+            return Err(format!(
+                "`{}` targets a `{}`, only paths within the store can be served",
+                source, target
+            ));
+        }
+    }
+    if status == 200 && !destination.starts_with('/') {
+        return Err(format!(
+            "`{}` proxies to `{}`, only paths within the store can be served",
+            source, destination
+        ));
+    }
+    let mut from = Vec::new();
+    let mut splat = None;
+    let source = if source.starts_with('/') {
+        source
+    } else {
+        format!("/{}", source)
+    };
+    let segments = source.split('/').collect::<Vec<&str>>();
+    for (index, segment) in segments.iter().enumerate() {
+        let last = index == segments.len() - 1;
+        if *segment == "**" && last {
+            from.push("*".to_string());
+        } else if let (true, Some(name)) = (
+            last,
+            segment
+                .strip_prefix(':')
+                .and_then(|name| name.strip_suffix('*')),
+        ) {
+            from.push("*".to_string());
+            splat = Some(format!(":{}", name));
+        } else if segment.strip_prefix(':').map(is_name) == Some(true) {
+            from.push(segment.to_string());
+        } else if segment.contains(['*', '?', '(', '{', '[', '!']) {
+            return Err(format!(
+                "`{}` uses the pattern `{}` which cannot be translated",
+                source, segment
+            ));
+        } else {
+            from.push(segment.to_string());
+        }
+    }
+    let to = match splat {
+        Some(splat) => destination.replace(&splat, ":splat"),
+        None => destination,
+    };
+    Ok(RedirectRule {
+        from: from.join("/"),
+        to,
+        status,
+        force,
+    })
+}
+
+fn is_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Translates a Vercel `source`, in the syntax of path-to-regexp, into a
+/// regular expression matching the whole path.
+fn path_to_regex(source: &str) -> Result<String, String> {
+    let mut regex = String::from("^");
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        let pattern = match c {
+            ':' => {
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    name.push(c);
+                }
+                if name.is_empty() {
+                    return Err(format!("has a `:` without a name in `{}`", source));
+                }
+                if chars.next_if_eq(&'(').is_some() {
+                    group(&mut chars, source)?
+                } else {
+                    "[^/]+".to_string()
+                }
+            }
+            '(' => group(&mut chars, source)?,
+            _ => {
+                regex.push_str(&regex::escape(&c.to_string()));
+                continue;
+            }
+        };
+        let prefix = if regex.ends_with('/') {
+            regex.pop();
+            "/"
+        } else {
+            ""
+        };
+        match chars.next_if(|c| matches!(c, '?' | '*' | '+')) {
+            Some(modifier) => regex.push_str(&format!("(?:{}(?:{})){}", prefix, pattern, modifier)),
+            None => regex.push_str(&format!("{}(?:{})", prefix, pattern)),
+        }
+    }
+    regex.push('$');
+    Ok(regex)
+}
+
+/// Reads the rest of a parenthesised group whose `(` has been consumed.
+fn group(
+    chars: &mut std::iter::Peekable<std::str::Chars<'_>>,
+    source: &str,
+) -> Result<String, String> {
+    let mut depth = 1;
+    let mut pattern = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                pattern.push(c);
+                if let Some(c) = chars.next() {
+                    pattern.push(c);
+                }
+                continue;
+            }
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(pattern);
+                }
+            }
+            _ => {}
+        }
+        pattern.push(c);
+    }
+    Err(format!("has an unclosed `(` in `{}`", source))
+}
+
+/// Translates a Firebase glob, which supports `**`, `*`, `?`, `{a,b}` and
+/// extglobs such as `@(a|b)`, into a regular expression matching the whole
+/// path.
+fn glob_to_regex(glob: &str) -> Result<String, String> {
+    let mut regex = String::from(if glob.starts_with(['/', '*']) {
+        "^"
+    } else {
+        "^/?"
+    });
+    // The text which closes each group that is currently open.
+    let mut groups: Vec<&str> = Vec::new();
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.next_if_eq(&'(').is_some() => {
+                regex.push_str("(?:");
+                groups.push(")*");
+            }
+            '*' if chars.next_if_eq(&'*').is_some() => regex.push_str(".*"),
+            '*' => regex.push_str("[^/]*"),
+            '?' if chars.next_if_eq(&'(').is_some() => {
+                regex.push_str("(?:");
+                groups.push(")?");
+            }
+            '?' => regex.push_str("[^/]"),
+            '@' | '+' if chars.peek() == Some(&'(') => {
+                chars.next();
+                regex.push_str("(?:");
+                groups.push(if c == '+' { ")+" } else { ")" });
+            }
+            '!' if chars.peek() == Some(&'(') => {
+                return Err(format!("`{}` uses a negated pattern", glob));
+            }
+            '{' => {
+                regex.push_str("(?:");
+                groups.push("}");
+            }
+            '|' if !groups.is_empty() => regex.push('|'),
+            ',' if groups.last() == Some(&"}") => regex.push('|'),
+            ')' | '}' if !groups.is_empty() => match groups.pop() {
+                Some("}") => regex.push(')'),
+                Some(close) => regex.push_str(close),
+                None => unreachable!(),
+            },
+            _ => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    if !groups.is_empty() {
+        return Err(format!("`{}` has an unclosed group", glob));
+    }
+    regex.push('$');
+    Ok(regex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path_matches(source: &str, path: &str) -> bool {
+        Regex::new(&path_to_regex(source).unwrap())
+            .unwrap()
+            .is_match(path)
+    }
+
+    fn glob_matches(glob: &str, path: &str) -> bool {
+        Regex::new(&glob_to_regex(glob).unwrap())
+            .unwrap()
+            .is_match(path)
+    }
+
+    fn rule(from: &str, to: &str, status: u16, force: bool) -> RedirectRule {
+        RedirectRule {
+            from: from.to_string(),
+            to: to.to_string(),
+            status,
+            force,
+        }
+    }
+
+    #[test]
+    fn path_to_regex_translates_parameters() {
+        assert!(path_matches("/blog/:slug", "/blog/post"));
+        assert!(!path_matches("/blog/:slug", "/blog/a/b"));
+        assert!(path_matches("/docs/:path*", "/docs"));
+        assert!(path_matches("/docs/:path*", "/docs/a/b"));
+        assert!(!path_matches("/docs/:path+", "/docs"));
+        assert!(path_matches("/assets/(.*)", "/assets/app.js"));
+        assert!(path_matches("/:file(.*\\.js)", "/app.js"));
+        assert!(!path_matches("/:file(.*\\.js)", "/app.css"));
+        assert!(path_to_regex("/a/:").is_err());
+        assert!(path_to_regex("/a/(b").is_err());
+    }
+
+    #[test]
+    fn glob_to_regex_translates_globs() {
+        assert!(glob_matches("**", "/a/b.html"));
+        assert!(glob_matches("/assets/**", "/assets/a/b.js"));
+        assert!(glob_matches("404.html", "/404.html"));
+        assert!(!glob_matches("/*.js", "/a/app.js"));
+        assert!(glob_matches("**/*.@(jpg|png)", "/img/a.png"));
+        assert!(!glob_matches("**/*.@(jpg|png)", "/img/a.gif"));
+        assert!(glob_matches("**/*.{css,js}", "/a.css"));
+        assert!(glob_matches("**/*.{css,js}", "/b/a.js"));
+        assert!(!glob_matches("**/*.{css,js}", "/a.html"));
+        assert!(glob_to_regex("**/!(a)").is_err());
+        assert!(glob_to_regex("{a,b").is_err());
+    }
+
+    #[test]
+    fn vercel_rules_translate_splats() {
+        assert_eq!(
+            vercel_rule("/old/:path*", "/new/:path*", 308, true),
+            Ok(rule("/old/*", "/new/:splat", 308, true))
+        );
+        assert_eq!(
+            vercel_rule("/old/(.*)", "/new/$1", 308, true),
+            Ok(rule("/old/*", "/new/:splat", 308, true))
+        );
+        assert_eq!(
+            vercel_rule("/blog/:slug", "/posts/:slug", 200, false),
+            Ok(rule("/blog/:slug", "/posts/:slug", 200, false))
+        );
+        assert!(vercel_rule("/a/:id(\\d+)", "/b", 308, true).is_err());
+    }
+
+    #[test]
+    fn firebase_rules_translate_globs() {
+        let config = |source: &str, destination: &str| {
+            serde_json::json!({ "source": source, "destination": destination })
+                .as_object()
+                .unwrap()
+                .clone()
+        };
+        assert_eq!(
+            firebase_rule(&config("/old/**", "/new"), 301, true),
+            Ok(rule("/old/*", "/new", 301, true))
+        );
+        assert_eq!(
+            firebase_rule(&config("/old/:rest*", "/new/:rest"), 301, true),
+            Ok(rule("/old/*", "/new/:splat", 301, true))
+        );
+        assert!(firebase_rule(&config("/**/*.@(a|b)", "/x"), 301, true).is_err());
+        assert!(firebase_rule(&config("**", "https://example.com"), 200, false).is_err());
+    }
+
+    #[test]
+    fn headers_with_invalid_names_are_unsupported() {
+        let config = serde_json::json!({
+            "headers": [
+                { "source": "/a", "headers": [{ "value": "1" }] },
+                { "source": "/b", "headers": [{ "key": "X-B", "value": "1" }] }
+            ]
+        });
+        let imported = vercel(&config).unwrap();
+        assert_eq!(imported.headers.len(), 1);
+        assert_eq!(imported.unsupported.len(), 1);
+    }
+
+    #[test]
+    fn imported_headers_match_the_urls_a_file_is_served_at() {
+        let config = serde_json::json!({
+            "headers": [{ "source": "/about", "headers": [{ "key": "X-About", "value": "1" }] }]
+        });
+        let mut rules = crate::headers::HeaderRules::default();
+        for (matcher, headers) in vercel(&config).unwrap().headers {
+            rules.push(matcher, headers);
+        }
+        assert!(rules
+            .headers_for("/about/index.html")
+            .contains_key("X-About"));
+        assert!(rules.headers_for("/about.html").contains_key("X-About"));
+        assert!(rules.headers_for("/index.html").is_empty());
+    }
+}
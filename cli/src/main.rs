@@ -1,22 +1,48 @@
-use base64;
+use access::ACCESS_FILE;
+use cache::CachePolicy;
 use clap::{arg, Command};
+use compute_file_server_metadata::{
+    metadata_key, preview_prefix, preview_slug, reserved_key, surrogate_key, Asset, AssetManifest,
+    Metadata, RedirectTable, Rollout, Site, SiteTable, ACCESS_KEY, CANARY_KEY, DEPLOYMENT_KEY,
+    HISTORY_KEY, MANIFEST_KEY, PREVIEWS_KEY, REDIRECTS_KEY, SCHEMA_VERSION, SITES_KEY,
+};
 use fastly_api::apis::configuration::{ApiKey, Configuration};
 use fastly_api::apis::version_api::{
     activate_service_version, clone_service_version, list_service_versions,
     ActivateServiceVersionParams, CloneServiceVersionParams, ListServiceVersionsParams,
 };
 use futures::{stream, StreamExt};
+use headers::{HeaderRules, HEADERS_FILE};
+use history::{Deployment, History};
 use httpdate::fmt_http_date;
+use mime::MimeTypes;
+use previews::{Preview, PreviewIndex};
+use purge::Purge;
+use redirects::REDIRECTS_FILE;
 use reqwest::Client;
 use sha2::{Digest, Sha256};
+use signed::PrivatePaths;
 use simple_error::bail;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::error::Error;
-use std::path::PathBuf;
-use tokio;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tokio::fs::File;
-use toml_edit;
 use walkdir::WalkDir;
 
+mod access;
+mod cache;
+mod headers;
+mod history;
+mod import;
+mod manifest;
+mod mime;
+mod previews;
+mod purge;
+mod redirects;
+mod signed;
+mod sites;
+
 const PARALLEL_REQUESTS: usize = 10;
 const RETRY_REQUESTS: usize = 5;
 
@@ -439,17 +465,6 @@ fn lookup(extn: &str) -> Option<&&str> {
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct Metadata {
-    #[serde(rename = "ETag")]
-    etag: String,
-    #[serde(rename = "Last-Modified")]
-    last_modified: String,
-    #[serde(rename = "Content-Type")]
-    content_type: Option<String>,
-}
-
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct KVStores {
@@ -492,7 +507,7 @@ async fn create_store(name: &str, token: &str) -> Result<String, Box<dyn std::er
             "Failed to create Object Store named `{}`. Response body contained `{}`",
             name,
             res.text().await?
-        ))
+        ));
     }
 }
 
@@ -527,10 +542,9 @@ async fn get_or_create_store(
                 }
             });
         // if store does not exist, create store
-        if res.is_none() {
-            create_store(name, token).await
-        } else {
-            Ok(res.unwrap())
+        match res {
+            Some(id) => Ok(id),
+            None => create_store(name, token).await,
         }
     }
 }
@@ -549,7 +563,6 @@ async fn get_active_version_of_service(
 
     let params = ListServiceVersionsParams {
         service_id: service_id.to_owned(),
-        ..Default::default()
     };
 
     let result = list_service_versions(cfg, params)
@@ -563,7 +576,7 @@ async fn get_active_version_of_service(
             }
         });
 
-    return Ok(result.expect("Service should have an active version to clone"));
+    Ok(result.expect("Service should have an active version to clone"))
 }
 
 async fn clone_version_of_service(
@@ -582,7 +595,6 @@ async fn clone_version_of_service(
     let params = CloneServiceVersionParams {
         service_id: service_id.to_owned(),
         version_id: version,
-        ..Default::default()
     };
 
     Ok(clone_service_version(cfg, params).await?.number.unwrap())
@@ -604,12 +616,292 @@ async fn activate_version_of_service(
     let params = ActivateServiceVersionParams {
         service_id: service_id.to_string(),
         version_id: version,
-        ..Default::default()
     };
 
     Ok(activate_service_version(cfg, params).await?.number.unwrap())
 }
 
+/// Returns whether `entry` is one of the files in the root of the site which
+/// configures the file server rather than being served itself.
+fn is_site_config(root: &Path, entry: &Path) -> bool {
+    entry == root.join(HEADERS_FILE)
+        || entry == root.join(REDIRECTS_FILE)
+        || entry == root.join(ACCESS_FILE)
+}
+
+/// Reads the `_headers` and `_redirects` files from `root`, adding the rules
+/// translated from the `vercel.json` or `firebase.json` file at `import`.
+fn read_site_config(
+    root: &Path,
+    import: Option<&PathBuf>,
+) -> Result<(HeaderRules, Option<RedirectTable>), Box<dyn std::error::Error>> {
+    let mut header_rules = HeaderRules::read(root)?;
+    let mut redirect_rules = redirects::read(root)?;
+    let mut trailing_slash = None;
+    let mut clean_urls = false;
+    if let Some(import) = import {
+        let imported = import::read(import)?;
+        for directive in &imported.unsupported {
+            eprintln!(
+                "[!] {} is not supported and was skipped: {}",
+                import.display(),
+                directive
+            );
+        }
+        for (matcher, headers) in imported.headers {
+            header_rules.push(matcher, headers);
+        }
+        if !imported.redirects.is_empty()
+            || imported.trailing_slash.is_some()
+            || imported.clean_urls
+        {
+            redirect_rules
+                .get_or_insert_with(Vec::new)
+                .extend(imported.redirects);
+        }
+        trailing_slash = imported.trailing_slash;
+        clean_urls = imported.clean_urls;
+    }
+    let redirect_table = redirect_rules.map(|rules| RedirectTable {
+        trailing_slash,
+        clean_urls,
+        ..RedirectTable::compile(rules)
+    });
+    Ok((header_rules, redirect_table))
+}
+
+/// Returns the value stored under `key`, or `None` if it does not exist.
+async fn get_key(
+    client: &Client,
+    store_id: &str,
+    token: &str,
+    key: &str,
+) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+    match get_bytes(client, store_id, token, key).await? {
+        Some(value) => Ok(Some(String::from_utf8(value)?)),
+        None => Ok(None),
+    }
+}
+
+/// The same as `get_key` for values which may not be UTF-8, such as files.
+async fn get_bytes(
+    client: &Client,
+    store_id: &str,
+    token: &str,
+    key: &str,
+) -> Result<Option<Vec<u8>>, Box<dyn Error + Send + Sync>> {
+    let encoded_key =
+        percent_encoding::utf8_percent_encode(key, percent_encoding::NON_ALPHANUMERIC);
+    let res = client
+        .get(format!(
+            "https://api.fastly.com/resources/stores/kv/{}/keys/{}",
+            store_id, encoded_key
+        ))
+        .header("Fastly-Key", token)
+        .send()
+        .await?;
+    if res.status() == 404 {
+        return Ok(None);
+    }
+    if !res.status().is_success() {
+        bail!(
+            "Error reading `{}`: Response Status: {} Response Body: {}",
+            key,
+            res.status(),
+            res.text().await?
+        );
+    }
+    Ok(Some(res.bytes().await?.to_vec()))
+}
+
+/// Returns every key which starts with `prefix`.
+async fn list_keys(
+    client: &Client,
+    store_id: &str,
+    token: &str,
+    prefix: &str,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut keys = Vec::new();
+    let mut cursor = None;
+    loop {
+        let mut query = vec![("prefix", prefix.to_string())];
+        if let Some(cursor) = cursor {
+            query.push(("cursor", cursor));
+        }
+        let res = client
+            .get(format!(
+                "https://api.fastly.com/resources/stores/kv/{}/keys",
+                store_id
+            ))
+            .query(&query)
+            .header("Accept", "application/json")
+            .header("Fastly-Key", token)
+            .send()
+            .await?;
+        if !res.status().is_success() {
+            bail!(
+                "Error listing keys: Response Status: {} Response Body: {}",
+                res.status(),
+                res.text().await?
+            );
+        }
+        let page = res.json::<serde_json::Value>().await?;
+        keys.extend(
+            page["data"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|key| key.as_str().map(str::to_string)),
+        );
+        cursor = page["meta"]["next_cursor"]
+            .as_str()
+            .filter(|cursor| !cursor.is_empty())
+            .map(str::to_string);
+        if cursor.is_none() {
+            return Ok(keys);
+        }
+    }
+}
+
+/// Returns the prefixes nested within `prefix` which other sites were
+/// uploaded under, or are registered for with `register-site`, so that
+/// pruning `prefix` leaves their files alone.
+async fn nested_prefixes(
+    client: &Client,
+    store_id: &str,
+    token: &str,
+    prefix: &str,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut prefixes = list_keys(client, store_id, token, DEPLOYMENT_KEY)
+        .await?
+        .into_iter()
+        .filter_map(|key| key.strip_prefix(DEPLOYMENT_KEY).map(str::to_string))
+        .collect::<Vec<String>>();
+    match get_key(client, store_id, token, SITES_KEY).await {
+        Ok(Some(table)) => prefixes.extend(
+            serde_json::from_str::<SiteTable>(&table)?
+                .into_values()
+                .filter_map(|site| site.prefix),
+        ),
+        Ok(None) => {}
+        Err(e) => {
+            bail!("{}", e);
+        }
+    }
+    let within = format!("{}/", prefix);
+    prefixes.retain(|nested| nested.starts_with(&within));
+    Ok(prefixes)
+}
+
+/// Returns whether `key` belongs to the site under one of `prefixes`.
+fn is_within(key: &str, prefixes: &[String]) -> bool {
+    prefixes
+        .iter()
+        .any(|prefix| key.starts_with(&format!("{}/", prefix)))
+}
+
+/// Returns the `--prefix` which files are uploaded under, such as `/blog`,
+/// or an empty string for the root of the store.
+fn key_prefix(sub_matches: &clap::ArgMatches) -> Result<String, Box<dyn std::error::Error>> {
+    let prefix = sub_matches
+        .get_one::<String>("prefix")
+        .map(|prefix| prefix.trim_end_matches('/'))
+        .unwrap_or("");
+    if !prefix.is_empty() && !prefix.starts_with('/') {
+        bail!(
+            "--prefix must start with `/`, such as `/blog`, but found `{}`",
+            prefix
+        );
+    }
+    Ok(prefix.to_string())
+}
+
+/// Returns the key of the file at `entry` within `root`, under `prefix`.
+fn file_key(root: &Path, entry: &Path, prefix: &str) -> String {
+    let normalised_entry = entry.strip_prefix(root).unwrap();
+    format!("{}/{}", prefix, normalised_entry.to_string_lossy())
+}
+
+/// Returns an identifier for a new deployment, which is stored in the
+/// metadata of every file it uploads and emitted as one of their
+/// `Surrogate-Key`s.
+fn new_deployment_id() -> String {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Stores `body` under `key`, retrying failed requests.
+async fn put_key(
+    client: &Client,
+    store_id: &str,
+    token: &str,
+    key: &str,
+    body: impl Into<Vec<u8>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let body = body.into();
+    let encoded_key =
+        percent_encoding::utf8_percent_encode(key, percent_encoding::NON_ALPHANUMERIC);
+    let mut counter = 0;
+    loop {
+        let res = client
+            .put(format!(
+                "https://api.fastly.com/resources/stores/kv/{}/keys/{}",
+                store_id, encoded_key
+            ))
+            .header("Content-Type", "application/json")
+            .header("Content-Length", body.len().to_string())
+            .header("Accept", "application/json")
+            .header("Fastly-Key", token)
+            .body(body.clone())
+            .send()
+            .await?;
+        if res.status() == 200 {
+            return Ok(());
+        }
+        counter += 1;
+        if counter > RETRY_REQUESTS {
+            bail!(
+                "Error uploading `{}`: Response Status: {} Response Body: {}",
+                key,
+                res.status(),
+                res.text().await?
+            );
+        }
+    }
+}
+
+/// Deletes `key`, succeeding if it did not exist.
+async fn delete_key(
+    client: &Client,
+    store_id: &str,
+    token: &str,
+    key: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let encoded_key =
+        percent_encoding::utf8_percent_encode(key, percent_encoding::NON_ALPHANUMERIC);
+    let res = client
+        .delete(format!(
+            "https://api.fastly.com/resources/stores/kv/{}/keys/{}",
+            store_id, encoded_key
+        ))
+        .header("Accept", "application/json")
+        .header("Fastly-Key", token)
+        .send()
+        .await?;
+    if !res.status().is_success() && res.status() != 404 {
+        bail!(
+            "Error deleting `{}`: Response Status: {} Response Body: {}",
+            key,
+            res.status(),
+            res.text().await?
+        );
+    }
+    Ok(())
+}
+
 fn cli() -> Command {
     Command::new("fastly-file-server")
         .about("Fastly File Server uploads files to Fastly for serving directly from within Fastly Compute applications. Upload any type of file: images, text, video etc and serve directly from Fastly. It is ideal for serving files built from a static site generator such as 11ty.")
@@ -626,7 +918,44 @@ fn cli() -> Command {
                 )
                 .arg_required_else_help(true)
                 .arg(arg!(--name <NAME>).required(true))
-                .arg(arg!(--token <TOKEN>)),
+                .arg(arg!(--token <TOKEN>))
+                .arg(arg!(--"service-id" <SERVICE_ID> "Service whose cache is purged once the upload completes"))
+                .arg(
+                    arg!(--purge <MODE> "Purge only the files which changed, or soft purge every file")
+                        .value_parser(["changed", "all"])
+                        .default_value("changed")
+                        .requires("service-id"),
+                )
+                .arg(arg!(--"purge-endpoint" <URL> "Base URL of the API used to purge").default_value(purge::DEFAULT_ENDPOINT))
+                .arg(arg!(--prune "Delete files under the prefix which are no longer in PATH"))
+                .arg(arg!(--preview <BRANCH> "Upload a preview of a branch alongside production"))
+                .arg(
+                    arg!(--import <FILE> "vercel.json or firebase.json file to translate into headers and redirects")
+                        .value_parser(clap::value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(--manifest <FILE> "Where to write the manifest of every file's size, content type and integrity")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .default_value(manifest::MANIFEST_FILE),
+                )
+                .arg(arg!(--"store-manifest" "Also store the manifest, so it can be read by the library"))
+                .arg(arg!(--prefix <PREFIX> "Upload the files under a path of the store, such as /blog"))
+                .arg(
+                    arg!(--"cache-control" <RULE> "Cache-Control for paths matching a pattern, as `PATTERN=VALUE`")
+                        .action(clap::ArgAction::Append),
+                )
+                .arg(
+                    arg!(--"surrogate-control" <RULE> "Surrogate-Control for paths matching a pattern, as `PATTERN=VALUE`")
+                        .action(clap::ArgAction::Append),
+                )
+                .arg(
+                    arg!(--mime <MIME> "Content-Type for files with an extension, as `EXTENSION=TYPE`")
+                        .action(clap::ArgAction::Append),
+                )
+                .arg(
+                    arg!(--private <PATTERN> "Only serve files matching a pattern for signed URLs")
+                        .action(clap::ArgAction::Append),
+                ),
         )
         .subcommand(
             Command::new("local")
@@ -639,7 +968,140 @@ fn cli() -> Command {
                 )
                 .arg_required_else_help(true)
                 .arg(arg!(--toml <TOML>).required(true).value_parser(clap::value_parser!(PathBuf)))
-                .arg(arg!(--name <NAME>).required(true)),
+                .arg(arg!(--name <NAME>).required(true))
+                .arg(
+                    arg!(--import <FILE> "vercel.json or firebase.json file to translate into headers and redirects")
+                        .value_parser(clap::value_parser!(PathBuf)),
+                )
+                .arg(
+                    arg!(--manifest <FILE> "Where to write the manifest of every file's size, content type and integrity")
+                        .value_parser(clap::value_parser!(PathBuf))
+                        .default_value(manifest::MANIFEST_FILE),
+                )
+                .arg(arg!(--"store-manifest" "Also store the manifest, so it can be read by the library"))
+                .arg(arg!(--prefix <PREFIX> "Upload the files under a path of the store, such as /blog"))
+                .arg(
+                    arg!(--"cache-control" <RULE> "Cache-Control for paths matching a pattern, as `PATTERN=VALUE`")
+                        .action(clap::ArgAction::Append),
+                )
+                .arg(
+                    arg!(--"surrogate-control" <RULE> "Surrogate-Control for paths matching a pattern, as `PATTERN=VALUE`")
+                        .action(clap::ArgAction::Append),
+                )
+                .arg(
+                    arg!(--mime <MIME> "Content-Type for files with an extension, as `EXTENSION=TYPE`")
+                        .action(clap::ArgAction::Append),
+                )
+                .arg(
+                    arg!(--private <PATTERN> "Only serve files matching a pattern for signed URLs")
+                        .action(clap::ArgAction::Append),
+                ),
+        )
+        .subcommand(
+            Command::new("previews")
+                .about("List or delete preview deployments")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("list")
+                        .about("List preview deployments")
+                        .arg(arg!(--name <NAME>).required(true))
+                        .arg(arg!(--token <TOKEN>)),
+                )
+                .subcommand(
+                    Command::new("delete")
+                        .about("Delete preview deployments and their files")
+                        .arg(arg!(--name <NAME>).required(true))
+                        .arg(arg!(--token <TOKEN>))
+                        .arg(arg!(branches: [BRANCH] ... "Branches whose previews are deleted"))
+                        .arg(
+                            arg!(--"older-than" <DAYS> "Also delete previews which have not been uploaded for this many days")
+                                .value_parser(clap::value_parser!(u64)),
+                        )
+                        .group(
+                            clap::ArgGroup::new("previews")
+                                .args(["branches", "older-than"])
+                                .required(true)
+                                .multiple(true),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("history")
+                .about("List the deployments uploaded to a store, newest first")
+                .arg(arg!(--name <NAME>).required(true))
+                .arg(arg!(--token <TOKEN>))
+                .arg(arg!(--prefix <PREFIX> "Prefix the site was uploaded under with upload --prefix"))
+                .arg(arg!(--preview <BRANCH> "List the deployments of a branch's preview instead"))
+                .arg(
+                    arg!(--limit <COUNT> "How many deployments to list")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("20"),
+                ),
+        )
+        .subcommand(
+            Command::new("canary")
+                .about("Roll out a preview deployment to a share of visitors")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("set")
+                        .about("Serve a preview to a percentage of visitors")
+                        .arg(arg!(--name <NAME>).required(true))
+                        .arg(arg!(--token <TOKEN>))
+                        .arg(arg!(--branch <BRANCH> "Branch whose preview is the canary").required(true))
+                        .arg(
+                            arg!(--weight <PERCENT> "Percentage of visitors served the canary")
+                                .value_parser(clap::value_parser!(u8).range(0..=100))
+                                .required(true),
+                        )
+                        .arg(arg!(--prefix <PREFIX> "Prefix the site was uploaded under with upload --prefix")),
+                )
+                .subcommand(
+                    Command::new("promote")
+                        .about("Replace production with the canary")
+                        .arg(arg!(--name <NAME>).required(true))
+                        .arg(arg!(--token <TOKEN>))
+                        .arg(arg!(--prefix <PREFIX> "Prefix the site was uploaded under with upload --prefix"))
+                        .arg(arg!(--"service-id" <SERVICE_ID> "Service whose cache is soft purged once production is replaced"))
+                        .arg(arg!(--"purge-endpoint" <URL> "Base URL of the API used to purge").default_value(purge::DEFAULT_ENDPOINT)),
+                )
+                .subcommand(
+                    Command::new("abort")
+                        .about("Serve production to every visitor again")
+                        .arg(arg!(--name <NAME>).required(true))
+                        .arg(arg!(--token <TOKEN>))
+                        .arg(arg!(--prefix <PREFIX> "Prefix the site was uploaded under with upload --prefix")),
+                ),
+        )
+        .subcommand(
+            Command::new("register-site")
+                .about("Serve a site for a hostname, from a prefix of the store or from another store")
+                .arg(arg!(--name <NAME>).required(true))
+                .arg(arg!(--token <TOKEN>))
+                .arg(arg!(--host <HOST> "Hostname, such as docs.example.com, *.example.com or * for the default").required(true))
+                .arg(arg!(--prefix <PREFIX> "Prefix the site was uploaded under with upload --prefix"))
+                .arg(arg!(--store <STORE> "Store the site was uploaded to").conflicts_with("prefix"))
+                .arg(arg!(--remove "Remove the hostname's site instead").conflicts_with_all(["prefix", "store"])),
+        )
+        .subcommand(
+            Command::new("sign")
+                .about("Sign a URL for a private file")
+                .arg(arg!(path: <PATH> "Path of the file, such as /downloads/report.pdf"))
+                .arg(arg!(--key <KEY> "Signing key, which is otherwise read from FILE_SERVER_SIGNING_KEY"))
+                .arg(
+                    arg!(--"expires-in" <SECONDS> "How long the URL stays valid")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("3600"),
+                ),
+        )
+        .subcommand(
+            Command::new("hash-password")
+                .about("Hash a password for a user in _access.json")
+                .arg(arg!(--password <PASSWORD> "Password to hash, which is otherwise read from stdin"))
+                .arg(
+                    arg!(--iterations <ITERATIONS> "PBKDF2 iterations, which every request for a protected path pays for")
+                        .value_parser(clap::value_parser!(u32).range(1..))
+                        .default_value("10000"),
+                ),
         )
         .subcommand(
             Command::new("link")
@@ -678,12 +1140,9 @@ async fn link(sub_matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error::
     let token = sub_matches
         .get_one::<String>("token")
         .map(|s| s.to_owned())
-        .or_else(|| match std::env::var("FASTLY_API_TOKEN") {
-            Ok(x) => Some(x),
-            Err(_) => None,
-        });
+        .or_else(|| std::env::var("FASTLY_API_TOKEN").ok());
     if token.is_none() {
-        bail!("Missing Fastly API token. Please provide an API token via the --token argument or the FASTLY_API_TOKEN environment variable.")
+        bail!("Missing Fastly API token. Please provide an API token via the --token argument or the FASTLY_API_TOKEN environment variable.");
     }
     let token = token.unwrap();
 
@@ -712,6 +1171,14 @@ async fn link(sub_matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error::
     Ok(())
 }
 
+/// A file which `upload` stored, and whether it differs from the file it
+/// replaced.
+struct UploadedFile {
+    path: String,
+    changed: bool,
+    asset: Asset,
+}
+
 async fn upload(sub_matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
     let name = sub_matches
         .get_one::<String>("name")
@@ -721,12 +1188,9 @@ async fn upload(sub_matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error
     let token = sub_matches
         .get_one::<String>("token")
         .map(|s| s.to_owned())
-        .or_else(|| match std::env::var("FASTLY_API_TOKEN") {
-            Ok(x) => Some(x),
-            Err(_) => None,
-        });
+        .or_else(|| std::env::var("FASTLY_API_TOKEN").ok());
     if token.is_none() {
-        bail!("Missing Fastly API token. Please provide an API token via the --token argument or the FASTLY_API_TOKEN environment variable.")
+        bail!("Missing Fastly API token. Please provide an API token via the --token argument or the FASTLY_API_TOKEN environment variable.");
     }
     let token = token.unwrap();
     let store_id = get_or_create_store(name, &token).await?;
@@ -735,32 +1199,73 @@ async fn upload(sub_matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error
         .get_one::<PathBuf>("path")
         .expect("required in clap");
 
-    let entries = WalkDir::new(path).follow_links(true)
+    let entries = WalkDir::new(path)
+        .follow_links(true)
         .into_iter()
         .filter_map(Result::ok)
         .filter(|e| !e.file_type().is_dir())
+        .filter(|e| !is_site_config(path, e.path()))
         .collect::<Vec<walkdir::DirEntry>>();
+    let (header_rules, redirect_table) =
+        read_site_config(path, sub_matches.get_one::<PathBuf>("import"))?;
+    let access_rules = access::read(path)?;
+    let preview = sub_matches.get_one::<String>("preview");
+    let prefix = match preview {
+        Some(branch) if preview_slug(branch).is_empty() => {
+            bail!("--preview expects a branch name, but found `{}`", branch);
+        }
+        Some(branch) => format!("{}{}", preview_prefix(branch), key_prefix(sub_matches)?),
+        None => key_prefix(sub_matches)?,
+    };
+    let uploaded = entries
+        .iter()
+        .map(|entry| file_key(path, entry.path(), &prefix))
+        .collect::<HashSet<String>>();
+    let header_rules = Arc::new(header_rules);
+    let cache_policy = Arc::new(CachePolicy::from_args(sub_matches)?);
+    let mime_types = Arc::new(MimeTypes::from_args(sub_matches)?);
+    let private_paths = Arc::new(PrivatePaths::from_args(sub_matches)?);
+    let deployment = new_deployment_id();
+    let service_id = sub_matches.get_one::<String>("service-id");
+    let purge = match sub_matches.get_one::<String>("purge").map(|s| s.as_str()) {
+        Some("all") => Purge::All,
+        _ => Purge::Changed,
+    };
+    let diff = service_id.is_some() && purge == Purge::Changed;
+    let changed = std::cell::RefCell::new(Vec::new());
+    let failed = std::cell::Cell::new(0);
+    let manifest = std::cell::RefCell::new(AssetManifest {
+        deployment: deployment.clone(),
+        ..Default::default()
+    });
 
     let pb = indicatif::ProgressBar::new(entries.len().try_into().unwrap());
     let client = Client::new();
 
     let bodies = stream::iter(entries)
-        .map(|entry| -> tokio::task::JoinHandle<Result<String, Box<dyn Error + Send + Sync>>> {
+        .map(|entry| -> tokio::task::JoinHandle<Result<UploadedFile, Box<dyn Error + Send + Sync>>> {
             let path = path.clone();
             let store_id = store_id.clone();
             let token = token.clone();
             let client = client.clone();
+            let header_rules = header_rules.clone();
+            let cache_policy = cache_policy.clone();
+            let mime_types = mime_types.clone();
+            let private_paths = private_paths.clone();
+            let prefix = prefix.clone();
+            let deployment = deployment.clone();
             tokio::spawn(async move {
                 let extension = entry.path().extension().map(|e| e.to_string_lossy().to_string()).unwrap_or("".to_string());
-                let normalised_entry = entry.path().strip_prefix(path).unwrap();
-                let normalised_path = "/".to_owned() + &normalised_entry.to_string_lossy();
+                // Rules are matched against the path within the uploaded folder.
+                let site_path = file_key(&path, entry.path(), "");
+                let normalised_path = format!("{}{}", prefix, site_path);
                 let key = percent_encoding::utf8_percent_encode(
                     &normalised_path,
                     percent_encoding::NON_ALPHANUMERIC,
                 );
-                let metadata_key = normalised_path.to_owned() + "__metadata__";
+                let metadata_path = metadata_key(&normalised_path);
                 let metadata_key = percent_encoding::utf8_percent_encode(
-                    &metadata_key,
+                    &metadata_path,
                     percent_encoding::NON_ALPHANUMERIC,
                 );
                 let file_contents = tokio::fs::read(entry.path()).await?;
@@ -768,32 +1273,67 @@ async fn upload(sub_matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error
                 let file_metadata = file.metadata().await?;
                 let length = file.metadata().await?.len();
                 let mut counter = 0;
-                let sha = Sha256::digest(file_contents);
-                let sha = base64::encode(&sha);
-                let metadata = serde_json::to_string(&Metadata {
+                let sha = Sha256::digest(&file_contents);
+                let sha = base64::encode(sha);
+                let content_type = mime_types.content_type_for(&extension, &file_contents);
+                let asset = Asset {
+                    size: length,
+                    content_type: content_type.clone(),
+                    integrity: manifest::integrity(&sha, &file_contents),
+                    etag: format!("W/\"{}\"", sha),
+                };
+                let metadata = Metadata {
+                    version: SCHEMA_VERSION,
                     etag: format!("W/\"{}\"", sha),
                     last_modified: fmt_http_date(file_metadata.modified()?),
-                    content_type: lookup(&extension).map(|content_type| content_type.to_string())
-                })?;
+                    cache_control: cache_policy.cache_control_for(&site_path, content_type.as_deref()),
+                    surrogate_control: cache_policy.surrogate_control_for(&site_path),
+                    content_type,
+                    headers: header_rules.headers_for(&site_path),
+                    deployment: Some(deployment),
+                    sha256: Some(sha),
+                    content_length: Some(length),
+                    private: private_paths.is_private(&site_path),
+                };
+                // A file has changed unless its previous metadata only differs by
+                // deployment or by the modification time of an identical file.
+                let changed = match diff {
+                    true => {
+                        let previous = get_key(&client, &store_id, &token, &metadata_path).await?;
+                        match previous.and_then(|previous| serde_json::from_str::<Metadata>(&previous).ok()) {
+                            Some(previous) => Metadata {
+                                last_modified: metadata.last_modified.clone(),
+                                deployment: metadata.deployment.clone(),
+                                ..previous
+                            } != metadata,
+                            None => true,
+                        }
+                    }
+                    false => true,
+                };
+                let metadata = serde_json::to_string(&metadata)?;
 
+                // The file is written before its metadata, so that the
+                // metadata of a file never describes a body which has not
+                // been written yet.
                 loop {
                     let res = client
                         .put(format!(
                             "https://api.fastly.com/resources/stores/kv/{}/keys/{}",
-                            store_id, metadata_key
+                            store_id, key
                         ))
                         .header("Content-Type", "application/json")
-                        .header("Content-Length", metadata.len().to_string())
+                        .header("Content-Length", length)
                         .header("Accept", "application/json")
                         .header("Fastly-Key", &token)
-                        .body(metadata.clone())
+                        .body(file.try_clone().await?)
                         .send()
                         .await?;
                     if res.status() != 200 {
-                        counter = counter + 1;
+                        counter += 1;
                         if counter > RETRY_REQUESTS {
                             bail!(
-                                "Error uploading metadata for file named `{}`: Response Status: {} Response Body: {}",
+                                "Error uploading file named `{}`: Response Status: {} Response Body: {}",
                                 normalised_path,
                                 res.status(),
                                 res.text().await?
@@ -808,29 +1348,31 @@ async fn upload(sub_matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error
                     let res = client
                         .put(format!(
                             "https://api.fastly.com/resources/stores/kv/{}/keys/{}",
-                            store_id, key
+                            store_id, metadata_key
                         ))
                         .header("Content-Type", "application/json")
-                        .header("Content-Length", length)
+                        .header("Content-Length", metadata.len().to_string())
                         .header("Accept", "application/json")
                         .header("Fastly-Key", &token)
-                        .body(file.try_clone().await?)
+                        .body(metadata.clone())
                         .send()
                         .await?;
                     if res.status() != 200 {
-                        counter = counter + 1;
+                        counter += 1;
                         if counter > RETRY_REQUESTS {
                             bail!(
-                                "Error uploading file named `{}`: Response Status: {} Response Body: {}",
+                                "Error uploading metadata for file named `{}`: Response Status: {} Response Body: {}",
                                 normalised_path,
                                 res.status(),
                                 res.text().await?
                             );
                         }
                     } else {
-                        return Ok::<String, Box<dyn std::error::Error + Send + Sync>>(
-                            normalised_path,
-                        );
+                        return Ok::<UploadedFile, Box<dyn std::error::Error + Send + Sync>>(UploadedFile {
+                            path: normalised_path,
+                            changed,
+                            asset,
+                        });
                     }
                 }
             })
@@ -840,17 +1382,194 @@ async fn upload(sub_matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error
     bodies
         .for_each(|b| async {
             match b {
-                Ok(Ok(normalised_entry)) => {
-                    pb.println(format!("[+] uploaded {}", normalised_entry));
+                Ok(Ok(file)) => {
+                    pb.println(format!("[+] uploaded {}", file.path));
                     pb.inc(1);
+                    manifest
+                        .borrow_mut()
+                        .files
+                        .insert(file.path.clone(), file.asset);
+                    if file.changed {
+                        changed.borrow_mut().push(file.path);
+                    }
+                }
+                Ok(Err(e)) => {
+                    eprintln!("Got a reqwest::Error: {}", e);
+                    failed.set(failed.get() + 1);
+                }
+                Err(e) => {
+                    eprintln!("Got a tokio::JoinError: {}", e);
+                    failed.set(failed.get() + 1);
                 }
-                Ok(Err(e)) => eprintln!("Got a reqwest::Error: {}", e),
-                Err(e) => eprintln!("Got a tokio::JoinError: {}", e),
             }
         })
         .await;
 
     pb.finish_with_message("done");
+
+    let mut pruned = 0;
+    if sub_matches.get_flag("prune") {
+        // Only keys under the prefix are listed, and those of sites nested
+        // within it are skipped, so files uploaded under other prefixes are
+        // never pruned.
+        let nested = nested_prefixes(&client, &store_id, &token, &prefix).await?;
+        for key in list_keys(&client, &store_id, &token, &format!("{}/", prefix)).await? {
+            let file = key.strip_suffix("__metadata__").unwrap_or(&key);
+            if uploaded.contains(file) || is_within(file, &nested) {
+                continue;
+            }
+            delete_key(&client, &store_id, &token, &key).await?;
+            if file == key {
+                println!("[-] pruned {}", file);
+                changed.borrow_mut().push(file.to_string());
+                pruned += 1;
+            }
+        }
+        println!("[+] pruned {} files", pruned);
+    }
+
+    let redirects_key = reserved_key(REDIRECTS_KEY, &prefix);
+    match redirect_table {
+        Some(table) => {
+            put_key(
+                &client,
+                &store_id,
+                &token,
+                &redirects_key,
+                serde_json::to_string(&table)?,
+            )
+            .await?;
+            println!(
+                "[+] uploaded {} rules from {}",
+                table.rules.len(),
+                REDIRECTS_FILE
+            );
+        }
+        None => delete_key(&client, &store_id, &token, &redirects_key).await?,
+    }
+
+    let access_key = reserved_key(ACCESS_KEY, &prefix);
+    match access_rules {
+        Some(rules) => {
+            put_key(
+                &client,
+                &store_id,
+                &token,
+                &access_key,
+                serde_json::to_string(&rules)?,
+            )
+            .await?;
+            println!("[+] uploaded {} rules from {}", rules.len(), ACCESS_FILE);
+        }
+        None => delete_key(&client, &store_id, &token, &access_key).await?,
+    }
+
+    let manifest = manifest.into_inner();
+    let files = manifest.files.len();
+    let bytes = manifest.files.values().map(|asset| asset.size).sum();
+    let manifest_hash = manifest::digest(&manifest)?;
+    let manifest = serde_json::to_string_pretty(&manifest)?;
+    let manifest_path = sub_matches
+        .get_one::<PathBuf>("manifest")
+        .expect("defaulted in clap");
+    std::fs::write(manifest_path, &manifest)?;
+    println!("[+] wrote {}", manifest_path.display());
+    let manifest_key = reserved_key(MANIFEST_KEY, &prefix);
+    match sub_matches.get_flag("store-manifest") {
+        true => put_key(&client, &store_id, &token, &manifest_key, manifest).await?,
+        false => delete_key(&client, &store_id, &token, &manifest_key).await?,
+    }
+
+    let (commit, branch) = history::git_revision(path);
+    let history_key = reserved_key(HISTORY_KEY, &prefix);
+    let mut log = read_history(&client, &store_id, &token, &history_key).await?;
+    history::append(
+        &mut log,
+        Deployment {
+            id: deployment.clone(),
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)?
+                .as_secs(),
+            commit,
+            branch,
+            user: history::user(path),
+            files,
+            failed: failed.get(),
+            pruned,
+            bytes,
+            manifest: manifest_hash,
+            promoted: None,
+        },
+    );
+    put_key(
+        &client,
+        &store_id,
+        &token,
+        &history_key,
+        serde_json::to_string(&log)?,
+    )
+    .await?;
+    put_key(
+        &client,
+        &store_id,
+        &token,
+        &reserved_key(DEPLOYMENT_KEY, &prefix),
+        deployment.clone(),
+    )
+    .await?;
+    println!("[+] recorded deployment {}", deployment);
+
+    if let Some(branch) = preview {
+        let mut index = read_previews(&client, &store_id, &token).await?;
+        index.insert(
+            preview_slug(branch),
+            Preview {
+                branch: branch.to_string(),
+                deployment: deployment.clone(),
+                updated: std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)?
+                    .as_secs(),
+            },
+        );
+        put_key(
+            &client,
+            &store_id,
+            &token,
+            PREVIEWS_KEY,
+            serde_json::to_string(&index)?,
+        )
+        .await?;
+        println!("[+] uploaded the preview of {}", branch);
+    }
+
+    if let Some(service_id) = service_id {
+        let endpoint = sub_matches
+            .get_one::<String>("purge-endpoint")
+            .expect("defaulted in clap");
+        match purge {
+            Purge::Changed => {
+                let keys = changed
+                    .into_inner()
+                    .iter()
+                    .map(|path| surrogate_key(path))
+                    .collect::<Vec<String>>();
+                purge::purge_keys(&client, endpoint, service_id, &token, &keys, false).await?;
+                println!("[+] purged {} changed files", keys.len());
+            }
+            Purge::All => {
+                purge::purge_keys(
+                    &client,
+                    endpoint,
+                    service_id,
+                    &token,
+                    &[format!("{}/", prefix)],
+                    true,
+                )
+                .await?;
+                println!("[+] soft purged every file");
+            }
+        }
+    }
     Ok(())
 }
 
@@ -868,25 +1587,73 @@ async fn local(sub_matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error:
         .get_one::<PathBuf>("toml")
         .expect("required in clap");
 
-    let entries = WalkDir::new(path).follow_links(true)
+    let entries = WalkDir::new(path)
+        .follow_links(true)
         .into_iter()
         .filter_map(Result::ok)
         .filter(|e| !e.file_type().is_dir())
+        .filter(|e| !is_site_config(path, e.path()))
         .collect::<Vec<walkdir::DirEntry>>();
+    let (header_rules, redirect_table) =
+        read_site_config(path, sub_matches.get_one::<PathBuf>("import"))?;
+    let access_rules = access::read(path)?;
+    let prefix = key_prefix(sub_matches)?;
+    let cache_policy = CachePolicy::from_args(sub_matches)?;
+    let mime_types = MimeTypes::from_args(sub_matches)?;
+    let private_paths = PrivatePaths::from_args(sub_matches)?;
+    let deployment = new_deployment_id();
+    let mut manifest = AssetManifest {
+        deployment: deployment.clone(),
+        ..Default::default()
+    };
 
     let mut toml = std::fs::read_to_string(toml_path)?.parse::<toml_edit::Document>()?;
     let mut local_server = toml
         .get_key_value("local_server")
         .map(|a| a.1.to_owned())
-        .unwrap_or_else(|| toml_edit::table());
+        .unwrap_or_else(toml_edit::table);
     let mut object_store = local_server
         .as_table_mut()
         .unwrap()
-        .get_key_value(&format!("object_store"))
+        .get_key_value("object_store")
         .map(|a| a.1.to_owned())
-        .unwrap_or_else(|| toml_edit::table());
+        .unwrap_or_else(toml_edit::table);
 
+    // Keep the entries of files uploaded under other prefixes.
+    let redirects_key = reserved_key(REDIRECTS_KEY, &prefix);
+    let access_key = reserved_key(ACCESS_KEY, &prefix);
+    let manifest_key = reserved_key(MANIFEST_KEY, &prefix);
+    let deployment_key = reserved_key(DEPLOYMENT_KEY, &prefix);
     let mut site = toml_edit::array();
+    if let Some(existing) = object_store
+        .get(name)
+        .and_then(|item| item.as_array_of_tables())
+    {
+        let within = format!("{}/", prefix);
+        let nested = existing
+            .iter()
+            .filter_map(|entry| entry.get("key").and_then(|key| key.as_str()))
+            .filter_map(|key| key.strip_prefix(DEPLOYMENT_KEY))
+            .filter(|nested| nested.starts_with(&within))
+            .map(str::to_string)
+            .collect::<Vec<String>>();
+        for entry in existing.iter() {
+            let key = entry.get("key").and_then(|key| key.as_str()).unwrap_or("");
+            if (!key.starts_with(&within) || is_within(key, &nested))
+                && ![
+                    redirects_key.as_str(),
+                    access_key.as_str(),
+                    manifest_key.as_str(),
+                    deployment_key.as_str(),
+                ]
+                .contains(&key)
+            {
+                site.as_array_of_tables_mut()
+                    .unwrap()
+                    .push(entry.to_owned());
+            }
+        }
+    }
     for entry in entries {
         let path = path.clone();
         let entry_path = entry.path().to_string_lossy().to_string();
@@ -895,19 +1662,38 @@ async fn local(sub_matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error:
             .extension()
             .map(|e| e.to_string_lossy().to_string())
             .unwrap_or("".to_string());
-        let normalised_entry = entry.path().strip_prefix(path).unwrap();
-        let normalised_path = "/".to_owned() + &normalised_entry.to_string_lossy();
+        // Rules are matched against the path within the uploaded folder.
+        let site_path = file_key(&path, entry.path(), "");
+        let normalised_path = format!("{}{}", prefix, site_path);
         let key = &normalised_path;
-        let metadata_key = normalised_path.to_owned() + "__metadata__";
+        let metadata_key = metadata_key(&normalised_path);
         let file_contents = tokio::fs::read(entry.path()).await?;
         let file = File::open(entry.path()).await?;
         let file_metadata = file.metadata().await?;
-        let sha = Sha256::digest(file_contents);
-        let sha = base64::encode(&sha);
+        let sha = Sha256::digest(&file_contents);
+        let sha = base64::encode(sha);
+        let content_type = mime_types.content_type_for(&extension, &file_contents);
+        manifest.files.insert(
+            normalised_path.clone(),
+            Asset {
+                size: file_metadata.len(),
+                content_type: content_type.clone(),
+                integrity: manifest::integrity(&sha, &file_contents),
+                etag: format!("W/\"{}\"", sha),
+            },
+        );
         let metadata = serde_json::to_string(&Metadata {
+            version: SCHEMA_VERSION,
             etag: format!("W/\"{}\"", sha),
             last_modified: fmt_http_date(file_metadata.modified()?),
-            content_type: lookup(&extension).map(|content_type| content_type.to_string()),
+            cache_control: cache_policy.cache_control_for(&site_path, content_type.as_deref()),
+            surrogate_control: cache_policy.surrogate_control_for(&site_path),
+            content_type,
+            headers: header_rules.headers_for(&site_path),
+            deployment: Some(deployment.clone()),
+            sha256: Some(sha),
+            content_length: Some(file_metadata.len()),
+            private: private_paths.is_private(&site_path),
         })?;
         let mut entry = toml_edit::table();
         entry
@@ -934,17 +1720,610 @@ async fn local(sub_matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error:
             .unwrap()
             .push(entry.as_table().unwrap().to_owned());
     }
+    if let Some(table) = redirect_table {
+        let mut entry = toml_edit::table();
+        entry
+            .as_table_mut()
+            .unwrap()
+            .insert("key", toml_edit::value(&redirects_key));
+        entry
+            .as_table_mut()
+            .unwrap()
+            .insert("data", toml_edit::value(serde_json::to_string(&table)?));
+        site.as_array_of_tables_mut()
+            .unwrap()
+            .push(entry.as_table().unwrap().to_owned());
+    }
+    if let Some(rules) = access_rules {
+        let mut entry = toml_edit::table();
+        entry
+            .as_table_mut()
+            .unwrap()
+            .insert("key", toml_edit::value(&access_key));
+        entry
+            .as_table_mut()
+            .unwrap()
+            .insert("data", toml_edit::value(serde_json::to_string(&rules)?));
+        site.as_array_of_tables_mut()
+            .unwrap()
+            .push(entry.as_table().unwrap().to_owned());
+    }
+    let manifest = serde_json::to_string_pretty(&manifest)?;
+    let manifest_path = sub_matches
+        .get_one::<PathBuf>("manifest")
+        .expect("defaulted in clap");
+    std::fs::write(manifest_path, &manifest)?;
+    if sub_matches.get_flag("store-manifest") {
+        let mut entry = toml_edit::table();
+        entry
+            .as_table_mut()
+            .unwrap()
+            .insert("key", toml_edit::value(&manifest_key));
+        entry
+            .as_table_mut()
+            .unwrap()
+            .insert("data", toml_edit::value(manifest));
+        site.as_array_of_tables_mut()
+            .unwrap()
+            .push(entry.as_table().unwrap().to_owned());
+    }
+    let mut entry = toml_edit::table();
+    entry
+        .as_table_mut()
+        .unwrap()
+        .insert("key", toml_edit::value(&deployment_key));
+    entry
+        .as_table_mut()
+        .unwrap()
+        .insert("data", toml_edit::value(&deployment));
+    site.as_array_of_tables_mut()
+        .unwrap()
+        .push(entry.as_table().unwrap().to_owned());
     object_store.as_table_mut().unwrap().insert(name, site);
     local_server
         .as_table_mut()
         .unwrap()
-        .insert(&"object_store", object_store);
+        .insert("object_store", object_store);
     toml.as_table_mut().insert("local_server", local_server);
     std::fs::write(toml_path, toml.to_string())?;
 
     Ok(())
 }
 
+/// Returns the index of preview deployments in the store.
+async fn read_previews(
+    client: &Client,
+    store_id: &str,
+    token: &str,
+) -> Result<PreviewIndex, Box<dyn std::error::Error>> {
+    match get_key(client, store_id, token, PREVIEWS_KEY).await {
+        Ok(Some(index)) => Ok(serde_json::from_str(&index)?),
+        Ok(None) => Ok(PreviewIndex::new()),
+        Err(e) => {
+            bail!("{}", e);
+        }
+    }
+}
+
+/// Returns the preview each site is rolling out as a canary, keyed by the
+/// site's prefix.
+async fn read_canaries(
+    client: &Client,
+    store_id: &str,
+    token: &str,
+) -> Result<BTreeMap<String, String>, Box<dyn std::error::Error>> {
+    let mut canaries = BTreeMap::new();
+    for key in list_keys(client, store_id, token, CANARY_KEY).await? {
+        let prefix = match key.strip_prefix(CANARY_KEY) {
+            Some(prefix) if prefix.is_empty() || prefix.starts_with('/') => prefix,
+            _ => continue,
+        };
+        match get_key(client, store_id, token, &key).await {
+            Ok(Some(rollout)) => {
+                let rollout: Rollout = serde_json::from_str(&rollout)?;
+                canaries.insert(prefix.to_string(), rollout.preview);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                bail!("{}", e);
+            }
+        }
+    }
+    Ok(canaries)
+}
+
+/// Returns the log of deployments stored under `key`.
+async fn read_history(
+    client: &Client,
+    store_id: &str,
+    token: &str,
+    key: &str,
+) -> Result<History, Box<dyn std::error::Error>> {
+    match get_key(client, store_id, token, key).await {
+        Ok(Some(log)) => Ok(serde_json::from_str(&log)?),
+        Ok(None) => Ok(History::new()),
+        Err(e) => {
+            bail!("{}", e);
+        }
+    }
+}
+
+async fn history(sub_matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let name = sub_matches
+        .get_one::<String>("name")
+        .map(|s| s.as_str())
+        .expect("required in clap");
+    let prefix = match sub_matches.get_one::<String>("preview") {
+        Some(branch) => format!("{}{}", preview_prefix(branch), key_prefix(sub_matches)?),
+        None => key_prefix(sub_matches)?,
+    };
+
+    let token = sub_matches
+        .get_one::<String>("token")
+        .map(|s| s.to_owned())
+        .or_else(|| std::env::var("FASTLY_API_TOKEN").ok());
+    if token.is_none() {
+        bail!("Missing Fastly API token. Please provide an API token via the --token argument or the FASTLY_API_TOKEN environment variable.");
+    }
+    let token = token.unwrap();
+    let store_id = get_or_create_store(name, &token).await?;
+    let client = Client::new();
+    let log = read_history(
+        &client,
+        &store_id,
+        &token,
+        &reserved_key(HISTORY_KEY, &prefix),
+    )
+    .await?;
+    let limit = *sub_matches
+        .get_one::<usize>("limit")
+        .expect("defaulted in clap");
+
+    for deployment in log.iter().rev().take(limit) {
+        let deployed = std::time::UNIX_EPOCH + std::time::Duration::from_secs(deployment.timestamp);
+        let revision = match (&deployment.branch, &deployment.commit) {
+            (Some(branch), Some(commit)) => format!("{}@{:.12}", branch, commit),
+            (None, Some(commit)) => format!("{:.12}", commit),
+            _ => "-".to_string(),
+        };
+        let mut files = format!("{} files, {} bytes", deployment.files, deployment.bytes);
+        if deployment.failed > 0 {
+            files.push_str(&format!(", {} failed", deployment.failed));
+        }
+        if deployment.pruned > 0 {
+            files.push_str(&format!(", {} pruned", deployment.pruned));
+        }
+        if let Some(branch) = &deployment.promoted {
+            files.push_str(&format!(", promoted from {}", branch));
+        }
+        println!(
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            deployment.id,
+            fmt_http_date(deployed),
+            deployment.user.as_deref().unwrap_or("-"),
+            revision,
+            files,
+            deployment.manifest
+        );
+    }
+    Ok(())
+}
+
+async fn previews(sub_matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let (command, sub_matches) = sub_matches.subcommand().expect("required in clap");
+    let name = sub_matches
+        .get_one::<String>("name")
+        .map(|s| s.as_str())
+        .expect("required in clap");
+
+    let token = sub_matches
+        .get_one::<String>("token")
+        .map(|s| s.to_owned())
+        .or_else(|| std::env::var("FASTLY_API_TOKEN").ok());
+    if token.is_none() {
+        bail!("Missing Fastly API token. Please provide an API token via the --token argument or the FASTLY_API_TOKEN environment variable.");
+    }
+    let token = token.unwrap();
+    let store_id = get_or_create_store(name, &token).await?;
+    let client = Client::new();
+    let mut index = read_previews(&client, &store_id, &token).await?;
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+
+    if command == "list" {
+        for (slug, preview) in &index {
+            let updated = std::time::UNIX_EPOCH + std::time::Duration::from_secs(preview.updated);
+            println!(
+                "{}\t{}\tdeployment {}\tuploaded {}",
+                slug,
+                preview.branch,
+                preview.deployment,
+                fmt_http_date(updated)
+            );
+        }
+        return Ok(());
+    }
+
+    let named = sub_matches
+        .get_many::<String>("branches")
+        .into_iter()
+        .flatten()
+        .map(|branch| preview_slug(branch))
+        .collect::<BTreeSet<String>>();
+    let mut stale = BTreeSet::new();
+    if let Some(days) = sub_matches.get_one::<u64>("older-than") {
+        let cutoff = now.as_secs().saturating_sub(days * 24 * 60 * 60);
+        stale.extend(
+            index
+                .iter()
+                .filter(|(_, preview)| preview.updated < cutoff)
+                .map(|(slug, _)| slug.to_string()),
+        );
+    }
+    let canaries = read_canaries(&client, &store_id, &token).await?;
+    let slugs = previews::deletable(&named, &stale, &canaries)?;
+    for slug in stale.difference(&slugs) {
+        println!(
+            "[!] kept the preview {}, which is being rolled out as a canary",
+            slug
+        );
+    }
+    for slug in slugs {
+        let prefix = preview_prefix(&slug);
+        let mut deleted = 0;
+        for key in list_keys(&client, &store_id, &token, &format!("{}/", prefix)).await? {
+            delete_key(&client, &store_id, &token, &key).await?;
+            deleted += 1;
+        }
+        // The site configuration of the preview, under every prefix it was
+        // uploaded with.
+        for name in [
+            REDIRECTS_KEY,
+            ACCESS_KEY,
+            MANIFEST_KEY,
+            HISTORY_KEY,
+            DEPLOYMENT_KEY,
+        ] {
+            let reserved = reserved_key(name, &prefix);
+            for key in list_keys(&client, &store_id, &token, &reserved).await? {
+                if key == reserved || key.starts_with(&format!("{}/", reserved)) {
+                    delete_key(&client, &store_id, &token, &key).await?;
+                }
+            }
+        }
+        match index.remove(&slug) {
+            Some(preview) => println!(
+                "[-] deleted the preview of {} and its {} keys",
+                preview.branch, deleted
+            ),
+            None => println!("[-] deleted {} keys of the preview {}", deleted, slug),
+        }
+    }
+    put_key(
+        &client,
+        &store_id,
+        &token,
+        PREVIEWS_KEY,
+        serde_json::to_string(&index)?,
+    )
+    .await?;
+    Ok(())
+}
+
+async fn canary(sub_matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let (command, sub_matches) = sub_matches.subcommand().expect("required in clap");
+    let name = sub_matches
+        .get_one::<String>("name")
+        .map(|s| s.as_str())
+        .expect("required in clap");
+    let prefix = key_prefix(sub_matches)?;
+
+    let token = sub_matches
+        .get_one::<String>("token")
+        .map(|s| s.to_owned())
+        .or_else(|| std::env::var("FASTLY_API_TOKEN").ok());
+    if token.is_none() {
+        bail!("Missing Fastly API token. Please provide an API token via the --token argument or the FASTLY_API_TOKEN environment variable.");
+    }
+    let token = token.unwrap();
+    let store_id = get_or_create_store(name, &token).await?;
+    let client = Client::new();
+    let canary_key = reserved_key(CANARY_KEY, &prefix);
+
+    if command == "set" {
+        let branch = sub_matches
+            .get_one::<String>("branch")
+            .expect("required in clap");
+        let weight = *sub_matches
+            .get_one::<u8>("weight")
+            .expect("required in clap");
+        let slug = preview_slug(branch);
+        if !read_previews(&client, &store_id, &token)
+            .await?
+            .contains_key(&slug)
+        {
+            bail!(
+                "{} has no preview. Please upload one with `upload --preview {}` first.",
+                branch,
+                branch
+            );
+        }
+        let rollout = Rollout {
+            branch: branch.to_string(),
+            preview: slug,
+            weight,
+        };
+        put_key(
+            &client,
+            &store_id,
+            &token,
+            &canary_key,
+            serde_json::to_string(&rollout)?,
+        )
+        .await?;
+        println!("[+] {}% of visitors are served {}", weight, branch);
+        return Ok(());
+    }
+
+    if command == "abort" {
+        delete_key(&client, &store_id, &token, &canary_key).await?;
+        println!("[-] every visitor is served production");
+        return Ok(());
+    }
+
+    let mut rollout = match get_key(&client, &store_id, &token, &canary_key).await {
+        Ok(Some(rollout)) => serde_json::from_str::<Rollout>(&rollout)?,
+        Ok(None) => {
+            bail!("There is no canary to promote. Please start one with `canary set`.");
+        }
+        Err(e) => {
+            bail!("{}", e);
+        }
+    };
+    // Every visitor is served the canary while production is overwritten, so
+    // that nobody is served a mix of both.
+    rollout.weight = 100;
+    put_key(
+        &client,
+        &store_id,
+        &token,
+        &canary_key,
+        serde_json::to_string(&rollout)?,
+    )
+    .await?;
+
+    let preview = preview_prefix(&rollout.preview);
+    let source = format!("{}{}", preview, prefix);
+    // Sites nested within the prefix are neither promoted nor pruned.
+    let nested_source = nested_prefixes(&client, &store_id, &token, &source).await?;
+    let nested = nested_prefixes(&client, &store_id, &token, &prefix).await?;
+    let mut promoted = HashSet::new();
+    let mut bytes = 0;
+    for key in list_keys(&client, &store_id, &token, &format!("{}/", source)).await? {
+        if is_within(&key, &nested_source) {
+            continue;
+        }
+        let value = match get_bytes(&client, &store_id, &token, &key).await {
+            Ok(Some(value)) => value,
+            Ok(None) => continue,
+            Err(e) => {
+                bail!("{}", e);
+            }
+        };
+        if !key.ends_with("__metadata__") {
+            bytes += value.len() as u64;
+        }
+        let target = key.strip_prefix(&preview).unwrap_or(&key).to_string();
+        put_key(&client, &store_id, &token, &target, value).await?;
+        promoted.insert(target);
+    }
+    let canary_deployment = get_key(
+        &client,
+        &store_id,
+        &token,
+        &reserved_key(DEPLOYMENT_KEY, &source),
+    )
+    .await;
+    let canary_deployment = match canary_deployment {
+        Ok(deployment) => deployment.unwrap_or_else(new_deployment_id),
+        Err(e) => {
+            bail!("{}", e);
+        }
+    };
+    let canary_upload = read_history(
+        &client,
+        &store_id,
+        &token,
+        &reserved_key(HISTORY_KEY, &source),
+    )
+    .await?
+    .pop()
+    .unwrap_or_default();
+    for name in [REDIRECTS_KEY, ACCESS_KEY, MANIFEST_KEY] {
+        let target = reserved_key(name, &prefix);
+        match get_key(&client, &store_id, &token, &reserved_key(name, &source)).await {
+            Ok(Some(value)) => put_key(&client, &store_id, &token, &target, value).await?,
+            Ok(None) => delete_key(&client, &store_id, &token, &target).await?,
+            Err(e) => {
+                bail!("{}", e);
+            }
+        }
+    }
+    let mut pruned = 0;
+    for key in list_keys(&client, &store_id, &token, &format!("{}/", prefix)).await? {
+        if promoted.contains(&key) || is_within(&key, &nested) {
+            continue;
+        }
+        delete_key(&client, &store_id, &token, &key).await?;
+        if !key.ends_with("__metadata__") {
+            pruned += 1;
+        }
+    }
+    delete_key(&client, &store_id, &token, &canary_key).await?;
+    let files = promoted
+        .iter()
+        .filter(|key| !key.ends_with("__metadata__"))
+        .count();
+    println!(
+        "[+] promoted {} to production, copying {} files and deleting {}",
+        rollout.branch, files, pruned
+    );
+
+    let history_key = reserved_key(HISTORY_KEY, &prefix);
+    let mut log = read_history(&client, &store_id, &token, &history_key).await?;
+    history::append(
+        &mut log,
+        Deployment {
+            id: canary_deployment.clone(),
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)?
+                .as_secs(),
+            user: history::user(Path::new(".")),
+            files,
+            failed: 0,
+            pruned,
+            bytes,
+            promoted: Some(rollout.branch.clone()),
+            ..canary_upload
+        },
+    );
+    put_key(
+        &client,
+        &store_id,
+        &token,
+        &history_key,
+        serde_json::to_string(&log)?,
+    )
+    .await?;
+    put_key(
+        &client,
+        &store_id,
+        &token,
+        &reserved_key(DEPLOYMENT_KEY, &prefix),
+        canary_deployment.clone(),
+    )
+    .await?;
+    println!("[+] recorded deployment {}", canary_deployment);
+
+    if let Some(service_id) = sub_matches.get_one::<String>("service-id") {
+        let endpoint = sub_matches
+            .get_one::<String>("purge-endpoint")
+            .expect("defaulted in clap");
+        purge::purge_keys(
+            &client,
+            endpoint,
+            service_id,
+            &token,
+            &[format!("{}/", prefix)],
+            true,
+        )
+        .await?;
+        println!("[+] soft purged every file");
+    }
+    Ok(())
+}
+
+async fn register_site(sub_matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let name = sub_matches
+        .get_one::<String>("name")
+        .map(|s| s.as_str())
+        .expect("required in clap");
+
+    let host = sites::normalise_host(
+        sub_matches
+            .get_one::<String>("host")
+            .expect("required in clap"),
+    )?;
+    let site = Site {
+        prefix: Some(key_prefix(sub_matches)?).filter(|prefix| !prefix.is_empty()),
+        store: sub_matches.get_one::<String>("store").cloned(),
+    };
+
+    let token = sub_matches
+        .get_one::<String>("token")
+        .map(|s| s.to_owned())
+        .or_else(|| std::env::var("FASTLY_API_TOKEN").ok());
+    if token.is_none() {
+        bail!("Missing Fastly API token. Please provide an API token via the --token argument or the FASTLY_API_TOKEN environment variable.");
+    }
+    let token = token.unwrap();
+    let store_id = get_or_create_store(name, &token).await?;
+
+    let client = Client::new();
+    let mut table = match get_key(&client, &store_id, &token, SITES_KEY).await {
+        Ok(Some(table)) => serde_json::from_str::<SiteTable>(&table)?,
+        Ok(None) => SiteTable::new(),
+        Err(e) => {
+            bail!("{}", e);
+        }
+    };
+    if sub_matches.get_flag("remove") {
+        if table.remove(&host).is_none() {
+            bail!("{} has no site", host);
+        }
+        println!("[-] removed the site for {}", host);
+    } else {
+        println!("[+] {} serves {}", host, site);
+        table.insert(host, site);
+    }
+    put_key(
+        &client,
+        &store_id,
+        &token,
+        SITES_KEY,
+        serde_json::to_string(&table)?,
+    )
+    .await?;
+    Ok(())
+}
+
+fn sign(sub_matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let path = sub_matches
+        .get_one::<String>("path")
+        .expect("required in clap");
+    if !path.starts_with('/') {
+        bail!("The path to sign must start with `/`, but found `{}`", path);
+    }
+
+    let key = sub_matches
+        .get_one::<String>("key")
+        .map(|s| s.to_owned())
+        .or_else(|| std::env::var("FILE_SERVER_SIGNING_KEY").ok());
+    if key.is_none() {
+        bail!("Missing signing key. Please provide the key via the --key argument or the FILE_SERVER_SIGNING_KEY environment variable.");
+    }
+    let key = key.unwrap();
+
+    let expires_in = sub_matches
+        .get_one::<u64>("expires-in")
+        .expect("defaulted in clap");
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    println!("{}", signed::sign(key.as_bytes(), path, now + expires_in));
+    Ok(())
+}
+
+fn hash_password(sub_matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let password = match sub_matches.get_one::<String>("password") {
+        Some(password) => password.to_owned(),
+        None => {
+            let mut password = String::new();
+            std::io::stdin().read_line(&mut password)?;
+            password.trim_end_matches(['\r', '\n']).to_string()
+        }
+    };
+    if password.is_empty() {
+        bail!(
+            "Missing password. Please provide the password via the --password argument or stdin."
+        );
+    }
+    let iterations = sub_matches
+        .get_one::<u32>("iterations")
+        .expect("defaulted in clap");
+    println!("{}", access::hash_password(&password, *iterations)?);
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let matches = cli().get_matches();
@@ -953,6 +2332,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         Some(("link", sub_matches)) => link(sub_matches).await,
         Some(("local", sub_matches)) => local(sub_matches).await,
         Some(("upload", sub_matches)) => upload(sub_matches).await,
+        Some(("register-site", sub_matches)) => register_site(sub_matches).await,
+        Some(("previews", sub_matches)) => previews(sub_matches).await,
+        Some(("history", sub_matches)) => history(sub_matches).await,
+        Some(("canary", sub_matches)) => canary(sub_matches).await,
+        Some(("sign", sub_matches)) => sign(sub_matches),
+        Some(("hash-password", sub_matches)) => hash_password(sub_matches),
         _ => unreachable!(),
     }
 }
@@ -0,0 +1,51 @@
+use compute_file_server_metadata::AssetManifest;
+use sha2::{Digest, Sha256, Sha384};
+
+/// The file the manifest is written to unless `--manifest` says otherwise.
+pub const MANIFEST_FILE: &str = "asset-manifest.json";
+
+/// Returns the Subresource Integrity metadata for `contents`, whose base64
+/// encoded SHA-256 digest has already been computed as `sha256`.
+pub fn integrity(sha256: &str, contents: &[u8]) -> String {
+    format!(
+        "sha256-{} sha384-{}",
+        sha256,
+        base64::encode(Sha384::digest(contents))
+    )
+}
+
+/// Returns the SHA-256 digest of the files in `manifest`, leaving out the id
+/// of its deployment, so that deployments of the same files have the same
+/// digest.
+pub fn digest(manifest: &AssetManifest) -> Result<String, serde_json::Error> {
+    let files = serde_json::to_vec(&manifest.files)?;
+    Ok(format!("sha256-{}", base64::encode(Sha256::digest(files))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use compute_file_server_metadata::Asset;
+
+    #[test]
+    fn digest_ignores_the_deployment() {
+        let mut manifest = AssetManifest {
+            deployment: "a".to_string(),
+            ..AssetManifest::default()
+        };
+        manifest.files.insert(
+            "/index.html".to_string(),
+            Asset {
+                size: 5,
+                etag: "\"abc\"".to_string(),
+                ..Asset::default()
+            },
+        );
+        let mut redeployed = manifest.clone();
+        redeployed.deployment = "b".to_string();
+        assert_eq!(digest(&manifest).unwrap(), digest(&redeployed).unwrap());
+
+        redeployed.files.get_mut("/index.html").unwrap().size = 6;
+        assert_ne!(digest(&manifest).unwrap(), digest(&redeployed).unwrap());
+    }
+}
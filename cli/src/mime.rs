@@ -0,0 +1,186 @@
+use simple_error::bail;
+use std::collections::HashMap;
+
+/// Chooses the `Content-Type` stored with each file, from the `--mime`
+/// overrides, then the built-in `MIMES` map and, for files without an
+/// extension, the file's first bytes.
+#[derive(Default, Debug, Clone)]
+pub struct MimeTypes {
+    overrides: HashMap<String, String>,
+}
+
+impl MimeTypes {
+    pub fn from_args(matches: &clap::ArgMatches) -> Result<MimeTypes, Box<dyn std::error::Error>> {
+        let mut overrides = HashMap::new();
+        for mime in matches.get_many::<String>("mime").into_iter().flatten() {
+            match mime.split_once('=') {
+                Some((extension, content_type))
+                    if !extension.trim().is_empty() && !content_type.trim().is_empty() =>
+                {
+                    overrides.insert(
+                        extension.trim().trim_start_matches('.').to_lowercase(),
+                        content_type.trim().to_string(),
+                    );
+                }
+                _ => {
+                    bail!(
+                    "--mime expects `EXTENSION=TYPE`, such as `webmanifest=application/manifest+json`, but found `{}`",
+                    mime
+                );
+                }
+            }
+        }
+        Ok(MimeTypes { overrides })
+    }
+
+    /// Returns the content type of a file with `extension` and `contents`,
+    /// adding `charset=utf-8` to text types when the contents are valid
+    /// UTF-8.
+    pub fn content_type_for(&self, extension: &str, contents: &[u8]) -> Option<String> {
+        let content_type = match self.overrides.get(&extension.trim().to_lowercase()) {
+            Some(content_type) => content_type.to_owned(),
+            None => match crate::lookup(extension) {
+                Some(content_type) => content_type.to_string(),
+                None if extension.is_empty() => sniff(contents)?.to_string(),
+                None => return None,
+            },
+        };
+        if is_text(&content_type)
+            && !content_type.contains("charset=")
+            && std::str::from_utf8(contents).is_ok()
+        {
+            return Some(format!("{}; charset=utf-8", content_type));
+        }
+        Some(content_type)
+    }
+}
+
+fn is_text(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or("").trim();
+    essence.starts_with("text/")
+        || essence.ends_with("javascript")
+        || essence.ends_with("json")
+        || essence.ends_with("xml")
+}
+
+/// Returns the content type of a file without an extension from its magic
+/// bytes, falling back to `text/plain` for UTF-8 text.
+pub fn sniff(contents: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: [(&[u8], &str); 14] = [
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"\x00\x00\x01\x00", "image/x-icon"),
+        (b"%PDF-", "application/pdf"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x00asm", "application/wasm"),
+        (b"wOFF", "font/woff"),
+        (b"wOF2", "font/woff2"),
+        (b"OggS", "audio/ogg"),
+        (b"ID3", "audio/mpeg"),
+        (b"\x1a\x45\xdf\xa3", "video/webm"),
+    ];
+    for (signature, content_type) in SIGNATURES {
+        if contents.starts_with(signature) {
+            return Some(content_type);
+        }
+    }
+    if contents.len() >= 12 && &contents[..4] == b"RIFF" && &contents[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+    if contents.len() >= 8 && &contents[4..8] == b"ftyp" {
+        return Some("video/mp4");
+    }
+    let text = std::str::from_utf8(contents).ok()?;
+    let start = text
+        .trim_start_matches('\u{feff}')
+        .trim_start()
+        .chars()
+        .take(14)
+        .collect::<String>()
+        .to_lowercase();
+    if start.starts_with("<!doctype html") || start.starts_with("<html") {
+        return Some("text/html");
+    }
+    if start.starts_with("<svg") {
+        return Some("image/svg+xml");
+    }
+    if start.starts_with("<?xml") {
+        return Some("application/xml");
+    }
+    if (start.starts_with('{') || start.starts_with('['))
+        && serde_json::from_str::<serde_json::Value>(text).is_ok()
+    {
+        return Some("application/json");
+    }
+    Some("text/plain")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniff_reads_magic_bytes_and_text() {
+        for (contents, content_type) in [
+            (&b"\x89PNG\r\n\x1a\n\0\0"[..], Some("image/png")),
+            (b"RIFF\0\0\0\0WEBPVP8 ", Some("image/webp")),
+            (b"\0\0\0\x18ftypmp42", Some("video/mp4")),
+            (b"\xef\xbb\xbf  <!DOCTYPE html><p>", Some("text/html")),
+            (
+                b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>",
+                Some("image/svg+xml"),
+            ),
+            (b"{\"name\": \"app\"}", Some("application/json")),
+            (b"{ not json", Some("text/plain")),
+            (b"hello", Some("text/plain")),
+            (b"\0\x01\x02\xff", None),
+        ] {
+            assert_eq!(sniff(contents), content_type, "{:?}", contents);
+        }
+    }
+
+    #[test]
+    fn content_type_for_adds_a_charset_to_utf8_text() {
+        let mimes = MimeTypes {
+            overrides: HashMap::from([
+                (
+                    "webmanifest".to_string(),
+                    "application/manifest+json".to_string(),
+                ),
+                (
+                    "txt".to_string(),
+                    "text/plain; charset=iso-8859-1".to_string(),
+                ),
+            ]),
+        };
+        for (extension, contents, content_type) in [
+            (
+                "html",
+                &b"<p>caf\xc3\xa9</p>"[..],
+                Some("text/html; charset=utf-8"),
+            ),
+            ("html", b"<p>caf\xe9</p>", Some("text/html")),
+            ("png", b"\x89PNG\r\n\x1a\n", Some("image/png")),
+            ("", b"<!doctype html>", Some("text/html; charset=utf-8")),
+            ("", b"\x89PNG\r\n\x1a\n", Some("image/png")),
+            ("", b"\0\x01\x02\xff", None),
+            (
+                "webmanifest",
+                b"{}",
+                Some("application/manifest+json; charset=utf-8"),
+            ),
+            ("txt", b"caf\xe9", Some("text/plain; charset=iso-8859-1")),
+            ("unknown-extension", b"hello", None),
+        ] {
+            assert_eq!(
+                mimes.content_type_for(extension, contents).as_deref(),
+                content_type,
+                "{}",
+                extension
+            );
+        }
+    }
+}
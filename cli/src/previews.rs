@@ -0,0 +1,80 @@
+use serde_derive::{Deserialize, Serialize};
+use simple_error::bail;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A preview deployment uploaded with `upload --preview <branch>`.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Preview {
+    pub branch: String,
+    pub deployment: String,
+    /// When the preview was last uploaded, in seconds since the Unix epoch.
+    pub updated: u64,
+}
+
+/// Every preview in a store, keyed by the slug of its branch.
+pub type PreviewIndex = BTreeMap<String, Preview>;
+
+/// Returns the previews to delete, out of those `named` on the command line
+/// and those which are `stale`, given the preview each site is rolling out as
+/// a canary, keyed by the site's prefix.
+///
+/// Deleting a canary would send its share of visitors to an empty prefix, so
+/// naming one is an error and stale ones are kept.
+pub fn deletable(
+    named: &BTreeSet<String>,
+    stale: &BTreeSet<String>,
+    canaries: &BTreeMap<String, String>,
+) -> Result<BTreeSet<String>, Box<dyn std::error::Error>> {
+    for (prefix, slug) in canaries {
+        if named.contains(slug) {
+            let site = match prefix.is_empty() {
+                true => "the root of the store".to_string(),
+                false => format!("--prefix {}", prefix),
+            };
+            bail!(
+                "the preview {} is the canary for {}. Please run `canary abort` or `canary promote` first.",
+                slug,
+                site
+            );
+        }
+    }
+    Ok(named
+        .union(stale)
+        .filter(|slug| !canaries.values().any(|canary| canary == *slug))
+        .cloned()
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(slugs: &[&str]) -> BTreeSet<String> {
+        slugs.iter().map(|slug| slug.to_string()).collect()
+    }
+
+    #[test]
+    fn deletable_refuses_a_named_canary() {
+        let canaries = BTreeMap::from([("/blog".to_string(), "feature-a".to_string())]);
+        let error = deletable(&set(&["feature-a", "feature-b"]), &set(&[]), &canaries)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("feature-a is the canary for --prefix /blog"));
+    }
+
+    #[test]
+    fn deletable_keeps_stale_canaries() {
+        let canaries = BTreeMap::from([(String::new(), "feature-a".to_string())]);
+        let slugs = deletable(
+            &set(&["feature-b"]),
+            &set(&["feature-a", "feature-c"]),
+            &canaries,
+        )
+        .unwrap();
+        assert_eq!(slugs, set(&["feature-b", "feature-c"]));
+        assert_eq!(
+            deletable(&set(&["feature-a"]), &set(&[]), &BTreeMap::new()).unwrap(),
+            set(&["feature-a"])
+        );
+    }
+}
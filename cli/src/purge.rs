@@ -0,0 +1,54 @@
+use reqwest::Client;
+use simple_error::bail;
+
+/// The Fastly API, which `--purge-endpoint` can replace with a mock.
+pub const DEFAULT_ENDPOINT: &str = "https://api.fastly.com";
+
+/// The most keys the API accepts in a single purge request.
+const KEYS_PER_REQUEST: usize = 256;
+
+/// What `upload` purges from the cache of the linked service once every
+/// file has been uploaded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Purge {
+    /// Purge the key of each file whose contents or metadata changed.
+    Changed,
+    /// Soft purge `/`, the key every file shares, so that all of them are
+    /// revalidated while stale copies can still be served.
+    All,
+}
+
+/// Purges `keys` from the cache of `service_id`, in batches.
+pub async fn purge_keys(
+    client: &Client,
+    endpoint: &str,
+    service_id: &str,
+    token: &str,
+    keys: &[String],
+    soft: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for batch in keys.chunks(KEYS_PER_REQUEST) {
+        let mut request = client
+            .post(format!(
+                "{}/service/{}/purge",
+                endpoint.trim_end_matches('/'),
+                service_id
+            ))
+            .header("Accept", "application/json")
+            .header("Fastly-Key", token)
+            .header("Surrogate-Key", batch.join(" "));
+        if soft {
+            request = request.header("Fastly-Soft-Purge", "1");
+        }
+        let res = request.send().await?;
+        if !res.status().is_success() {
+            bail!(
+                "Error purging {} keys: Response Status: {} Response Body: {}",
+                batch.len(),
+                res.status(),
+                res.text().await?
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,158 @@
+use compute_file_server_metadata::RedirectRule;
+use simple_error::bail;
+use std::path::Path;
+
+/// The name of the file, in the root of the uploaded folder, which lists
+/// redirects and rewrites in the same format as Netlify and Cloudflare
+/// Pages.
+///
+/// ```text
+/// # from               to                  status
+/// /home                /                   301
+/// /news/*              /blog/:splat        301
+/// /blog/:year/:slug    /posts/:slug        302
+/// /app/*               /app/index.html     200
+/// /legacy              /404.html           404!
+/// ```
+///
+/// The status defaults to 301. A trailing `!` forces the rule to apply even
+/// when a file exists at the requested path.
+pub const REDIRECTS_FILE: &str = "_redirects";
+
+/// Reads the rules from the `_redirects` file in `root`, returning `None` if
+/// it does not exist.
+pub fn read(root: &Path) -> Result<Option<Vec<RedirectRule>>, Box<dyn std::error::Error>> {
+    let path = root.join(REDIRECTS_FILE);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    Ok(Some(parse(&std::fs::read_to_string(path)?)?))
+}
+
+pub fn parse(contents: &str) -> Result<Vec<RedirectRule>, Box<dyn std::error::Error>> {
+    let mut rules = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parts = line.split_whitespace().collect::<Vec<&str>>();
+        if let Some(condition) = parts.iter().find(|part| part.contains('=')) {
+            bail!(
+                "{} line {}: conditions such as `{}` are not supported",
+                REDIRECTS_FILE,
+                number + 1,
+                condition
+            );
+        }
+        let (from, to, status) = match parts[..] {
+            [from, to] => (from, to, "301"),
+            [from, to, status] => (from, to, status),
+            _ => {
+                bail!(
+                    "{} line {}: expected `from to [status]` but found `{}`",
+                    REDIRECTS_FILE,
+                    number + 1,
+                    line
+                );
+            }
+        };
+        if !from.starts_with('/') {
+            bail!(
+                "{} line {}: `{}` must be a path starting with `/`",
+                REDIRECTS_FILE,
+                number + 1,
+                from
+            );
+        }
+        if from.contains('*') && (!from.ends_with("/*") || from.matches('*').count() > 1) {
+            bail!(
+                "{} line {}: `*` is only supported as the last segment of `{}`, such as `/news/*`",
+                REDIRECTS_FILE,
+                number + 1,
+                from
+            );
+        }
+        let force = status.ends_with('!');
+        let status = match status.trim_end_matches('!').parse::<u16>() {
+            Ok(status) if (200..600).contains(&status) => status,
+            _ => {
+                bail!(
+                    "{} line {}: `{}` is not a valid status code",
+                    REDIRECTS_FILE,
+                    number + 1,
+                    status
+                );
+            }
+        };
+        if status == 200 && !to.starts_with('/') {
+            bail!(
+                "{} line {}: rewriting to `{}` is not supported, only paths within the store can be served with a 200",
+                REDIRECTS_FILE,
+                number + 1,
+                to
+            );
+        }
+        rules.push(RedirectRule {
+            from: from.to_string(),
+            to: to.to_string(),
+            status,
+            force,
+        });
+    }
+    Ok(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_reads_statuses_and_force() {
+        let rules = parse(
+            "# from  to  status\n\n/home  /\n/news/*  /blog/:splat  302\n/app/*  /app/index.html  200!\n",
+        )
+        .unwrap();
+        assert_eq!(
+            rules,
+            vec![
+                RedirectRule {
+                    from: "/home".to_string(),
+                    to: "/".to_string(),
+                    status: 301,
+                    force: false,
+                },
+                RedirectRule {
+                    from: "/news/*".to_string(),
+                    to: "/blog/:splat".to_string(),
+                    status: 302,
+                    force: false,
+                },
+                RedirectRule {
+                    from: "/app/*".to_string(),
+                    to: "/app/index.html".to_string(),
+                    status: 200,
+                    force: true,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_rejects_splats_within_a_segment() {
+        assert!(parse("/news*  /blog").is_err());
+        assert!(parse("/news/*/old  /blog").is_err());
+        assert!(parse("/news/**  /blog").is_err());
+        assert!(parse("/*  /index.html  200").is_ok());
+    }
+
+    #[test]
+    fn parse_rejects_unsupported_rules() {
+        assert!(parse("/home").is_err());
+        assert!(parse("home  /").is_err());
+        assert!(parse("/home  /  999").is_err());
+        assert!(parse("/home  /  abc").is_err());
+        assert!(parse("/home  /fr  302  Language=fr").is_err());
+        assert!(parse("/proxy  https://example.com/  200").is_err());
+    }
+}
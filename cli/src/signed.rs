@@ -0,0 +1,98 @@
+use crate::headers::matches;
+use hmac::{Hmac, Mac};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use sha2::Sha256;
+use simple_error::bail;
+
+/// The paths given to `--private`, whose files the library only serves for
+/// requests signed with `sign`.
+#[derive(Default, Debug, Clone)]
+pub struct PrivatePaths {
+    patterns: Vec<String>,
+}
+
+impl PrivatePaths {
+    pub fn from_args(
+        matches: &clap::ArgMatches,
+    ) -> Result<PrivatePaths, Box<dyn std::error::Error>> {
+        let mut patterns = Vec::new();
+        for pattern in matches.get_many::<String>("private").into_iter().flatten() {
+            if !pattern.starts_with('/') {
+                bail!(
+                    "--private expects a pattern, such as `/downloads/*`, but found `{}`",
+                    pattern
+                );
+            }
+            patterns.push(pattern.to_owned());
+        }
+        Ok(PrivatePaths { patterns })
+    }
+
+    pub fn is_private(&self, path: &str) -> bool {
+        self.patterns.iter().any(|pattern| matches(pattern, path))
+    }
+}
+
+/// The characters which are percent-encoded in the path of a signed URL.
+const PATH: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'/')
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// Returns `path` with the `expires` and `sig` query parameters which the
+/// library's `SigningKey` accepts until `expires`, in seconds since the Unix
+/// epoch.
+///
+/// `path` may be given percent-encoded or not. The decoded path is signed,
+/// as the library decodes the requested path before checking it, and the
+/// returned URL is percent-encoded.
+pub fn sign(key: &[u8], path: &str, expires: u64) -> String {
+    let path = percent_decode_str(path).decode_utf8_lossy();
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}\n{}", expires, path).as_bytes());
+    let signature = base64::encode_config(mac.finalize().into_bytes(), base64::URL_SAFE_NO_PAD);
+    format!(
+        "{}?expires={}&sig={}",
+        utf8_percent_encode(&path, PATH),
+        expires,
+        signature
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_the_path_and_expiry() {
+        // The library's `verify_signature` tests accept this URL.
+        assert_eq!(
+            sign(b"secret", "/downloads/report.pdf", 2_000_000_000),
+            "/downloads/report.pdf?expires=2000000000&sig=7x5mYHzO6i5O-FpQU2Xvc5bfbCH68FkPyriKtAfy95M"
+        );
+        assert_ne!(
+            sign(b"secret", "/downloads/other.pdf", 2_000_000_000),
+            sign(b"secret", "/downloads/report.pdf", 2_000_000_000)
+        );
+    }
+
+    #[test]
+    fn signs_the_decoded_path_and_encodes_the_url() {
+        // The library's `verify_signature` tests accept this URL.
+        let signed = "/downloads/Q3%20report%20%C3%BC.pdf?expires=2000000000&sig=zouFSLiUS5yOxfuvde7NGNB_s7WPUORxknannhz2WsI";
+        assert_eq!(
+            sign(b"secret", "/downloads/Q3 report ü.pdf", 2_000_000_000),
+            signed
+        );
+        assert_eq!(
+            sign(
+                b"secret",
+                "/downloads/Q3%20report%20%C3%BC.pdf",
+                2_000_000_000
+            ),
+            signed
+        );
+    }
+}
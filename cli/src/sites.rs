@@ -0,0 +1,15 @@
+use simple_error::bail;
+
+/// Returns `host` in the form the library matches, checking that any
+/// wildcard is a leading `*.` or the `*` default.
+pub fn normalise_host(host: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let host = host.trim().trim_end_matches('.').to_ascii_lowercase();
+    let labels = host.strip_prefix("*.").unwrap_or(&host);
+    if host.is_empty() || (host != "*" && (labels.contains('*') || labels.contains(':'))) {
+        bail!(
+            "--host expects a hostname such as `docs.example.com`, `*.example.com` or `*`, but found `{}`",
+            host
+        );
+    }
+    Ok(host)
+}
@@ -1,4 +1,4 @@
-use c_at_e_file_server::get;
+use compute_file_server::get;
 use fastly::{Error, Request, Response};
 
 #[fastly::main]
@@ -6,9 +6,9 @@ fn main(request: Request) -> Result<Response, Error> {
     if let Ok(fastly_service_version) = std::env::var("FASTLY_SERVICE_VERSION") {
         println!("FASTLY_SERVICE_VERSION: {}", fastly_service_version);
     }
-    let mut response = get("site", request)?;
+    let response = get("site", request)?;
     return match response {
-        Some(response) => {
+        Some(mut response) => {
             // Enable Dynamic Compression -- https://developer.fastly.com/learning/concepts/compression/#dynamic-compression
             response.set_header("x-compress-hint", "on");
             Ok(response)
@@ -0,0 +1,58 @@
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+
+/// Requires credentials for every path under `prefix`, as listed in an
+/// `_access.json` file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccessRule {
+    pub prefix: String,
+    #[serde(flatten)]
+    pub auth: Auth,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Auth {
+    Basic(Basic),
+    Jwt(Jwt),
+}
+
+/// HTTP Basic authentication against a list of users and their PBKDF2
+/// password hashes, as produced by the CLI's `hash-password` command.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Basic {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub realm: Option<String>,
+    pub users: BTreeMap<String, String>,
+}
+
+/// A JSON Web Token, read from an `Authorization: Bearer` header or from
+/// `cookie`, which must be signed with `key` and carry the configured
+/// `iss` and `aud` claims.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Jwt {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub realm: Option<String>,
+    pub algorithm: Algorithm,
+    /// The shared secret for HS256, or the PEM encoded public key for RS256.
+    pub key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audience: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cookie: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Algorithm {
+    HS256,
+    RS256,
+}
+
+/// The realm challenges use when a rule does not name one.
+pub const DEFAULT_REALM: &str = "Restricted";
@@ -0,0 +1,13 @@
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+
+/// A preview deployment which the library serves, instead of production, to
+/// `weight` percent of visitors.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rollout {
+    #[serde(default)]
+    pub branch: String,
+    /// The slug of the preview, which its files are stored under.
+    pub preview: String,
+    pub weight: u8,
+}
@@ -0,0 +1,243 @@
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+
+mod access;
+mod canary;
+mod manifest;
+mod redirects;
+mod sites;
+
+pub use access::{AccessRule, Algorithm, Auth, Basic, Jwt, DEFAULT_REALM};
+pub use canary::Rollout;
+pub use manifest::{Asset, AssetManifest};
+pub use redirects::{RedirectMatch, RedirectRule, RedirectTable};
+pub use sites::{Site, SiteTable};
+
+/// The key the compiled `_redirects` file is stored under.
+pub const REDIRECTS_KEY: &str = "__redirects__";
+/// The key the rules from an `_access.json` file are stored under.
+pub const ACCESS_KEY: &str = "__access__";
+/// The key the asset manifest is stored under with `--store-manifest`.
+pub const MANIFEST_KEY: &str = "__manifest__";
+/// The key the table of sites written by `register-site` is stored under.
+pub const SITES_KEY: &str = "__sites__";
+/// The key the index of preview deployments is stored under, which is also
+/// the start of every preview's prefix.
+pub const PREVIEWS_KEY: &str = "__previews__";
+/// The key the canary being rolled out is stored under.
+pub const CANARY_KEY: &str = "__canary__";
+/// The key the log of deployments made with `upload` is stored under.
+pub const HISTORY_KEY: &str = "__history__";
+/// The key the id of the latest deployment is stored under, so that the
+/// library can read it without reading the whole log.
+pub const DEPLOYMENT_KEY: &str = "__deployment__";
+
+/// The version of [`Metadata`] written by this crate. Metadata uploaded
+/// before the schema was versioned is read as version 0.
+///
+/// Version 2 stores every value of each header in [`Metadata::headers`].
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Returns the key the metadata of the file stored under `key` is stored
+/// under.
+pub fn metadata_key(key: &str) -> String {
+    format!("{}__metadata__", key)
+}
+
+/// Returns the `Surrogate-Key` the library emits for the file stored under
+/// `key`, which the CLI purges when the file changes. Keys are separated by
+/// spaces, so spaces within a key are encoded.
+pub fn surrogate_key(key: &str) -> String {
+    key.replace(' ', "%20")
+}
+
+/// Returns the key a reserved entry such as `__redirects__` is stored under
+/// for the site uploaded with `--prefix prefix`, such as
+/// `__redirects__/blog` for `/blog`.
+///
+/// The key never starts with `/`, so it cannot collide with a file.
+pub fn reserved_key(name: &str, prefix: &str) -> String {
+    format!("{}{}", name, prefix.trim_end_matches('/'))
+}
+
+/// Returns `branch` as it appears in the URLs and keys of its preview, with
+/// every run of characters other than lowercase letters and digits replaced
+/// by `-`, so that `feature/Login` becomes `feature-login`.
+pub fn preview_slug(branch: &str) -> String {
+    let mut slug = String::new();
+    for c in branch.to_lowercase().chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    // A DNS label is at most 63 characters long.
+    slug.truncate(63);
+    slug.trim_end_matches('-').to_string()
+}
+
+/// Returns the prefix the preview of `branch` is uploaded under, such as
+/// `__previews__/feature-login`.
+///
+/// The prefix does not start with `/`, so a preview can never collide with,
+/// or be pruned by, a production upload.
+pub fn preview_prefix(branch: &str) -> String {
+    format!("{}/{}", PREVIEWS_KEY, preview_slug(branch))
+}
+
+/// The metadata the CLI stores alongside each file, describing how the
+/// library should serve it.
+///
+/// Every field has a default and unknown fields are ignored, so metadata
+/// written by an older or newer CLI can always be read.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Metadata {
+    #[serde(rename = "Version")]
+    pub version: u32,
+    #[serde(rename = "ETag")]
+    pub etag: String,
+    #[serde(rename = "Last-Modified")]
+    pub last_modified: String,
+    #[serde(rename = "Content-Type", skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// The size of the file in bytes.
+    #[serde(rename = "Content-Length", skip_serializing_if = "Option::is_none")]
+    pub content_length: Option<u64>,
+    #[serde(rename = "Cache-Control", skip_serializing_if = "Option::is_none")]
+    pub cache_control: Option<String>,
+    #[serde(rename = "Surrogate-Control", skip_serializing_if = "Option::is_none")]
+    pub surrogate_control: Option<String>,
+    /// Headers set for the file's path by the `_headers` file, with every
+    /// value of a header which is set more than once, such as `Set-Cookie`.
+    #[serde(
+        rename = "Headers",
+        skip_serializing_if = "BTreeMap::is_empty",
+        deserialize_with = "header_values"
+    )]
+    pub headers: BTreeMap<String, Vec<String>>,
+    /// The deployment which uploaded the file.
+    #[serde(rename = "Deployment", skip_serializing_if = "Option::is_none")]
+    pub deployment: Option<String>,
+    /// The base64 encoded SHA-256 digest of the file.
+    #[serde(rename = "SHA-256", skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Whether the file is only served for requests with a valid signature.
+    #[serde(rename = "Private", skip_serializing_if = "std::ops::Not::not")]
+    pub private: bool,
+}
+
+/// Reads the values of each header in [`Metadata::headers`], which versions
+/// before 2 stored as a single string.
+fn header_values<'de, D>(deserializer: D) -> Result<BTreeMap<String, Vec<String>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Values {
+        One(String),
+        Many(Vec<String>),
+    }
+    let headers = <BTreeMap<String, Values> as serde::Deserialize>::deserialize(deserializer)?;
+    Ok(headers
+        .into_iter()
+        .map(|(name, values)| match values {
+            Values::One(value) => (name, vec![value]),
+            Values::Many(values) => (name, values),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preview_slug_makes_a_dns_label_of_the_branch() {
+        for (branch, slug) in [
+            ("feature/Login", "feature-login"),
+            ("release/v1.2.0", "release-v1-2-0"),
+            ("--fix__Bug--", "fix-bug"),
+            ("café", "caf"),
+            ("///", ""),
+        ] {
+            assert_eq!(preview_slug(branch), slug, "{}", branch);
+        }
+        assert_eq!(preview_slug(&"a".repeat(100)), "a".repeat(63));
+        // A separator left at the end by the truncation is dropped.
+        assert_eq!(
+            preview_slug(&format!("{}/b", "a".repeat(62))),
+            "a".repeat(62)
+        );
+        assert_eq!(
+            preview_prefix("feature/Login"),
+            "__previews__/feature-login"
+        );
+    }
+
+    #[test]
+    fn metadata_reads_payloads_written_before_the_schema_was_versioned() {
+        let metadata: Metadata = serde_json::from_str(
+            r#"{"ETag":"\"abc\"","Last-Modified":"Tue, 15 Nov 1994 12:45:26 GMT","Content-Type":"text/html","Headers":{"X-Frame-Options":"DENY"}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            metadata,
+            Metadata {
+                version: 0,
+                etag: "\"abc\"".to_string(),
+                last_modified: "Tue, 15 Nov 1994 12:45:26 GMT".to_string(),
+                content_type: Some("text/html".to_string()),
+                headers: BTreeMap::from([(
+                    "X-Frame-Options".to_string(),
+                    vec!["DENY".to_string()]
+                )]),
+                ..Metadata::default()
+            }
+        );
+        assert_eq!(
+            serde_json::from_str::<Metadata>("{}").unwrap(),
+            Metadata::default()
+        );
+    }
+
+    #[test]
+    fn metadata_ignores_unknown_fields() {
+        let metadata: Metadata = serde_json::from_str(
+            r#"{"Version":99,"ETag":"\"abc\"","Content-Encoding":"br","Future":{"a":1}}"#,
+        )
+        .unwrap();
+        assert_eq!(metadata.version, 99);
+        assert_eq!(metadata.etag, "\"abc\"");
+    }
+
+    #[test]
+    fn metadata_round_trips() {
+        let metadata = Metadata {
+            version: SCHEMA_VERSION,
+            etag: "\"abc\"".to_string(),
+            last_modified: "Tue, 15 Nov 1994 12:45:26 GMT".to_string(),
+            content_type: Some("text/html; charset=utf-8".to_string()),
+            content_length: Some(42),
+            cache_control: Some("public, max-age=60".to_string()),
+            surrogate_control: Some("max-age=31536000".to_string()),
+            headers: BTreeMap::from([(
+                "Set-Cookie".to_string(),
+                vec!["a=1; Path=/".to_string(), "b=2; Path=/".to_string()],
+            )]),
+            deployment: Some("1700000000".to_string()),
+            sha256: Some("47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=".to_string()),
+            private: true,
+        };
+        let json = serde_json::to_string(&metadata).unwrap();
+        assert_eq!(serde_json::from_str::<Metadata>(&json).unwrap(), metadata);
+        // Empty fields are left out, so that metadata stays small.
+        assert_eq!(
+            serde_json::to_string(&Metadata::default()).unwrap(),
+            r#"{"Version":0,"ETag":"","Last-Modified":""}"#
+        );
+    }
+}
@@ -0,0 +1,23 @@
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+
+/// Describes every file in a deployment, keyed by path, so that build tools
+/// can add `integrity` attributes and check what was deployed.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AssetManifest {
+    pub deployment: String,
+    pub files: BTreeMap<String, Asset>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Asset {
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// The Subresource Integrity metadata of the file, such as
+    /// `sha256-... sha384-...`, for use in an `integrity` attribute.
+    pub integrity: String,
+    pub etag: String,
+}
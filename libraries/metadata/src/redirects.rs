@@ -0,0 +1,256 @@
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use std::collections::HashMap;
+
+/// A rule from a `_redirects` file, or imported from another host's
+/// configuration file.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RedirectRule {
+    pub from: String,
+    pub to: String,
+    pub status: u16,
+    pub force: bool,
+}
+
+/// The rules from a `_redirects` file, followed by any imported from another
+/// host's configuration file, along with indexes so that the library only has
+/// to test the few rules which could match a request.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RedirectTable {
+    pub rules: Vec<RedirectRule>,
+    /// The first rule for each `from` which has no placeholders or splat.
+    pub exact: HashMap<String, usize>,
+    /// The remaining rules, keyed by the first segment of `from`. Rules whose
+    /// first segment is a placeholder or splat are keyed by `*`.
+    pub segments: HashMap<String, Vec<usize>>,
+    /// Whether directory indexes are redirected to the path with (`true`)
+    /// or without (`false`) a trailing slash, as imported from a
+    /// `trailingSlash` setting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trailing_slash: Option<bool>,
+    /// Whether `/page.html` is served as, and redirected to, `/page`, as
+    /// imported from a `cleanUrls` setting.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub clean_urls: bool,
+}
+
+/// A rule which matched a request, along with its destination.
+pub struct RedirectMatch<'a> {
+    pub rule: &'a RedirectRule,
+    /// `to` with its placeholders and splat filled in.
+    pub location: String,
+}
+
+impl RedirectTable {
+    pub fn compile(rules: Vec<RedirectRule>) -> RedirectTable {
+        let mut table = RedirectTable::default();
+        for (index, rule) in rules.iter().enumerate() {
+            let from = normalise(&rule.from);
+            if !from.contains(['*', ':']) {
+                table.exact.entry(from.to_string()).or_insert(index);
+                continue;
+            }
+            let first = from.trim_start_matches('/').split('/').next().unwrap_or("");
+            let bucket = if first.starts_with(':') || first == "*" {
+                "*"
+            } else {
+                first
+            };
+            table
+                .segments
+                .entry(bucket.to_string())
+                .or_default()
+                .push(index);
+        }
+        table.rules = rules;
+        table
+    }
+
+    /// Returns the first rule, in the order of the `_redirects` file, which
+    /// matches `path`.
+    ///
+    /// Only the rules indexed under `path` itself and under its first
+    /// segment are tested, so large tables cost no more than small ones.
+    pub fn find(&self, path: &str) -> Option<RedirectMatch<'_>> {
+        let path = normalise(path);
+        let first = path.trim_start_matches('/').split('/').next().unwrap_or("");
+        let mut candidates = self
+            .exact
+            .get(path)
+            .into_iter()
+            .copied()
+            .collect::<Vec<usize>>();
+        for bucket in [first, "*"] {
+            if let Some(indexes) = self.segments.get(bucket) {
+                candidates.extend(indexes);
+            }
+        }
+        candidates.sort_unstable();
+        for index in candidates {
+            let rule = &self.rules[index];
+            if let Some(params) = match_rule(normalise(&rule.from), path) {
+                return Some(RedirectMatch {
+                    rule,
+                    location: fill(&rule.to, &params),
+                });
+            }
+        }
+        None
+    }
+}
+
+/// Removes the trailing slash from `path`, as `/about` and `/about/` match
+/// the same rules.
+fn normalise(path: &str) -> &str {
+    if path.len() > 1 {
+        path.trim_end_matches('/')
+    } else {
+        path
+    }
+}
+
+/// Matches `path` against `from` segment by segment, returning the values of
+/// its `:placeholder`s and of its `*` as `splat`.
+fn match_rule(from: &str, path: &str) -> Option<Vec<(String, String)>> {
+    let mut params = Vec::new();
+    let mut path_segments = path.split('/');
+    for segment in from.split('/') {
+        if segment == "*" {
+            let rest = path_segments.collect::<Vec<&str>>().join("/");
+            params.push(("splat".to_string(), rest));
+            return Some(params);
+        }
+        let value = path_segments.next()?;
+        if let Some(name) = segment.strip_prefix(':') {
+            if value.is_empty() {
+                return None;
+            }
+            params.push((name.to_string(), value.to_string()));
+        } else if segment != value {
+            return None;
+        }
+    }
+    if path_segments.next().is_some() {
+        return None;
+    }
+    Some(params)
+}
+
+/// Replaces each `:name` in `to` with its matched value. A name runs for as
+/// long as there are letters, digits and `_`, so that `:id` is not filled in
+/// at the start of `:idx`, and names which were not matched are kept as they
+/// are.
+fn fill(to: &str, params: &[(String, String)]) -> String {
+    let mut location = String::with_capacity(to.len());
+    let mut rest = to;
+    while let Some(index) = rest.find(':') {
+        location.push_str(&rest[..index]);
+        let after = &rest[index + 1..];
+        let end = after
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(after.len());
+        let name = &after[..end];
+        match params.iter().find(|(param, _)| param == name) {
+            Some((_, value)) if !name.is_empty() => location.push_str(value),
+            _ => {
+                location.push(':');
+                location.push_str(name);
+            }
+        }
+        rest = &after[end..];
+    }
+    location.push_str(rest);
+    location
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(from: &str, to: &str, status: u16, force: bool) -> RedirectRule {
+        RedirectRule {
+            from: from.to_string(),
+            to: to.to_string(),
+            status,
+            force,
+        }
+    }
+
+    fn location(table: &RedirectTable, path: &str) -> Option<String> {
+        table.find(path).map(|found| found.location)
+    }
+
+    #[test]
+    fn exact_rules_ignore_trailing_slashes() {
+        let table = RedirectTable::compile(vec![rule("/home/", "/", 301, false)]);
+        assert_eq!(location(&table, "/home").as_deref(), Some("/"));
+        assert_eq!(location(&table, "/home/").as_deref(), Some("/"));
+        assert_eq!(location(&table, "/homes"), None);
+    }
+
+    #[test]
+    fn splats_match_the_rest_of_the_path() {
+        let table = RedirectTable::compile(vec![rule("/news/*", "/blog/:splat", 301, false)]);
+        assert_eq!(
+            location(&table, "/news/2024/launch").as_deref(),
+            Some("/blog/2024/launch")
+        );
+        assert_eq!(location(&table, "/news").as_deref(), Some("/blog/"));
+        assert_eq!(location(&table, "/newsletter"), None);
+
+        let table = RedirectTable::compile(vec![rule("/*", "/index.html", 200, false)]);
+        assert_eq!(
+            location(&table, "/any/path").as_deref(),
+            Some("/index.html")
+        );
+    }
+
+    #[test]
+    fn placeholders_match_one_segment_each() {
+        let table = RedirectTable::compile(vec![
+            rule("/blog/:year/:slug", "/posts/:slug?year=:year", 302, false),
+            rule("/:lang/about", "/about-:lang", 301, false),
+        ]);
+        assert_eq!(
+            location(&table, "/blog/2024/launch").as_deref(),
+            Some("/posts/launch?year=2024")
+        );
+        assert_eq!(location(&table, "/blog/2024"), None);
+        assert_eq!(location(&table, "/blog/2024/launch/more"), None);
+        assert_eq!(location(&table, "/blog//launch"), None);
+        assert_eq!(location(&table, "/fr/about").as_deref(), Some("/about-fr"));
+    }
+
+    #[test]
+    fn fill_only_replaces_whole_names() {
+        let table = RedirectTable::compile(vec![rule("/a/:id", "/b/:id/:idx", 301, false)]);
+        assert_eq!(location(&table, "/a/1").as_deref(), Some("/b/1/:idx"));
+
+        let params = vec![("slug".to_string(), "x".to_string())];
+        assert_eq!(fill("/:slug/:slugs/:slug_2", &params), "/x/:slugs/:slug_2");
+        assert_eq!(
+            fill("https://example.com:8080/:slug", &params),
+            "https://example.com:8080/x"
+        );
+    }
+
+    #[test]
+    fn the_first_matching_rule_wins() {
+        let table = RedirectTable::compile(vec![
+            rule("/docs/*", "/v2/:splat", 301, false),
+            rule("/docs/intro", "/start", 302, true),
+            rule("/docs/intro", "/ignored", 301, false),
+        ]);
+        let found = table.find("/docs/intro").unwrap();
+        assert_eq!(found.location, "/v2/intro");
+        assert_eq!((found.rule.status, found.rule.force), (301, false));
+
+        let table = RedirectTable::compile(vec![
+            rule("/legacy", "/404.html", 404, true),
+            rule("/legacy", "/ignored", 301, false),
+        ]);
+        let found = table.find("/legacy").unwrap();
+        assert_eq!(found.location, "/404.html");
+        assert_eq!((found.rule.status, found.rule.force), (404, true));
+    }
+}
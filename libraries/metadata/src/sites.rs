@@ -0,0 +1,27 @@
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use std::collections::BTreeMap;
+
+/// Maps each hostname, such as `docs.example.com`, `*.example.com` or the
+/// `*` default, to a site.
+pub type SiteTable = BTreeMap<String, Site>;
+
+/// Where the files of a site are, either under a prefix of the store, as
+/// uploaded with `--prefix`, or in a store of their own.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Site {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store: Option<String>,
+}
+
+impl std::fmt::Display for Site {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.prefix, &self.store) {
+            (Some(prefix), _) => write!(f, "prefix {}", prefix),
+            (None, Some(store)) => write!(f, "store {}", store),
+            (None, None) => write!(f, "the root of the store"),
+        }
+    }
+}
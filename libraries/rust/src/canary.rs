@@ -0,0 +1,72 @@
+use crate::layers::Layers;
+use compute_file_server_metadata::{reserved_key, Rollout, CANARY_KEY};
+use fastly::{Error, Request};
+
+/// Splits traffic between production and a canary deployment, once the CLI's
+/// `canary set` command has chosen the preview to roll out and its weight.
+///
+/// Each visitor is given a bucket from 0 to 99, kept in a cookie, and is
+/// served the canary while their bucket is below the weight. Visitors
+/// therefore never mix files from both deployments, and raising the weight
+/// only ever moves visitors onto the canary.
+///
+/// ```no_run
+/// use compute_file_server::{Canary, FileServer};
+/// let server = FileServer::new("site").canary(Canary::default().cookie("bucket"));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Canary {
+    cookie: String,
+    max_age: u32,
+}
+
+impl Default for Canary {
+    fn default() -> Self {
+        return Canary {
+            cookie: "canary".to_string(),
+            max_age: 30 * 24 * 60 * 60,
+        };
+    }
+}
+
+impl Canary {
+    /// Sets the name of the cookie which keeps each visitor's bucket,
+    /// defaulting to `canary`.
+    pub fn cookie(mut self, name: &str) -> Self {
+        self.cookie = name.to_string();
+        return self;
+    }
+
+    /// Sets how many seconds a visitor keeps their bucket, defaulting to 30
+    /// days.
+    pub fn max_age(mut self, seconds: u32) -> Self {
+        self.max_age = seconds;
+        return self;
+    }
+
+    /// Returns the bucket of the visitor who sent `request`, along with the
+    /// `Set-Cookie` which assigns it when they did not have one yet.
+    pub(crate) fn bucket(&self, request: &Request) -> Result<(u8, Option<String>), Error> {
+        let bucket = crate::cookie(request, &self.cookie)
+            .and_then(|bucket| bucket.parse::<u8>().ok())
+            .filter(|bucket| *bucket < 100);
+        if let Some(bucket) = bucket {
+            return Ok((bucket, None));
+        }
+        let mut random = [0; 2];
+        getrandom::getrandom(&mut random)?;
+        let bucket = (u16::from_le_bytes(random) % 100) as u8;
+        let cookie = format!(
+            "{}={}; Path=/; Max-Age={}; SameSite=Lax; HttpOnly",
+            self.cookie, bucket, self.max_age
+        );
+        return Ok((bucket, Some(cookie)));
+    }
+}
+
+pub(crate) fn lookup_rollout(layers: &Layers, prefix: &str) -> Result<Option<Rollout>, Error> {
+    return match layers.lookup(&reserved_key(CANARY_KEY, prefix))? {
+        Some(rollout) => Ok(Some(serde_json::from_str(&rollout.into_string())?)),
+        None => Ok(None),
+    };
+}
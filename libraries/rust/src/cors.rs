@@ -0,0 +1,264 @@
+use fastly::http::Method;
+use fastly::{Request, Response};
+
+/// A CORS policy applied to every response by
+/// [`FileServer::cors`](crate::FileServer::cors).
+///
+/// ```no_run
+/// use compute_file_server::{Cors, FileServer};
+/// let server = FileServer::new("site").cors(
+///     Cors::origins(["https://example.com", "https://*.example.com"])
+///         .credentials(true)
+///         .max_age(600),
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cors {
+    /// The allowed origins, where `*` matches any run of characters. `None`
+    /// allows every origin.
+    origins: Option<Vec<String>>,
+    credentials: bool,
+    exposed_headers: Vec<String>,
+    max_age: Option<u32>,
+}
+
+impl Cors {
+    /// Allows requests from any origin.
+    pub fn any() -> Self {
+        return Cors {
+            origins: None,
+            credentials: false,
+            exposed_headers: vec!["Content-Range".to_string(), "ETag".to_string()],
+            max_age: None,
+        };
+    }
+
+    /// Allows requests from the listed origins, such as
+    /// `https://example.com`, or from origins matching a pattern such as
+    /// `https://*.example.com`.
+    pub fn origins<I, S>(origins: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        return Cors {
+            origins: Some(origins.into_iter().map(Into::into).collect()),
+            ..Cors::any()
+        };
+    }
+
+    /// Sets whether requests may include credentials such as cookies.
+    ///
+    /// Credentials are only allowed for the origins listed with
+    /// [`Cors::origins`], and never by [`Cors::any`] or a bare `*` pattern,
+    /// as any website could otherwise read the files a visitor has access
+    /// to, such as those protected by access rules.
+    pub fn credentials(mut self, credentials: bool) -> Self {
+        self.credentials = credentials;
+        return self;
+    }
+
+    /// Sets the response headers which scripts may read, defaulting to
+    /// `Content-Range` and `ETag`.
+    pub fn expose_headers<I, S>(mut self, headers: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.exposed_headers = headers.into_iter().map(Into::into).collect();
+        return self;
+    }
+
+    /// Sets how many seconds browsers may cache the answer to a preflight.
+    pub fn max_age(mut self, seconds: u32) -> Self {
+        self.max_age = Some(seconds);
+        return self;
+    }
+
+    /// Returns whether responses allow credentials, which requires an
+    /// explicit list of origins.
+    fn allows_credentials(&self) -> bool {
+        return self.credentials
+            && self
+                .origins
+                .as_ref()
+                .is_some_and(|origins| origins.iter().all(|pattern| pattern != "*"));
+    }
+
+    /// Returns the `Access-Control-Allow-Origin` for a request from
+    /// `origin`, or `None` if the origin is not allowed.
+    fn allow_origin(&self, origin: Option<&str>) -> Option<String> {
+        let origins = match &self.origins {
+            Some(origins) => origins,
+            None => return Some("*".to_string()),
+        };
+        let origin = origin?;
+        return match origins.iter().any(|pattern| matches(pattern, origin)) {
+            true => Some(origin.to_string()),
+            false => None,
+        };
+    }
+
+    /// Adds the CORS headers for a request from `origin` to `response`.
+    pub(crate) fn apply(&self, origin: Option<&str>, response: &mut Response) {
+        let allow_origin = self.allow_origin(origin);
+        if allow_origin.as_deref() != Some("*") {
+            crate::add_vary(response, "Origin");
+        }
+        let allow_origin = match allow_origin {
+            Some(allow_origin) => allow_origin,
+            None => return,
+        };
+        response.set_header("access-control-allow-origin", allow_origin);
+        if self.allows_credentials() {
+            response.set_header("access-control-allow-credentials", "true");
+        }
+        if !self.exposed_headers.is_empty() {
+            response.set_header(
+                "access-control-expose-headers",
+                self.exposed_headers.join(", "),
+            );
+        }
+    }
+
+    /// Returns the answer to a preflight request.
+    pub(crate) fn preflight(&self, request: &Request) -> Response {
+        let mut response = Response::from_status(204);
+        response.set_header(
+            "vary",
+            "Origin, Access-Control-Request-Method, Access-Control-Request-Headers",
+        );
+        let allow_origin = match self.allow_origin(request.get_header_str("origin")) {
+            Some(allow_origin) => allow_origin,
+            None => return response,
+        };
+        response.set_header("access-control-allow-origin", allow_origin);
+        response.set_header("access-control-allow-methods", "GET, HEAD");
+        if let Some(headers) = request.get_header_str("access-control-request-headers") {
+            response.set_header("access-control-allow-headers", headers);
+        }
+        if self.allows_credentials() {
+            response.set_header("access-control-allow-credentials", "true");
+        }
+        if let Some(max_age) = self.max_age {
+            response.set_header("access-control-max-age", max_age.to_string());
+        }
+        return response;
+    }
+}
+
+/// Returns whether `request` is a CORS preflight.
+pub(crate) fn is_preflight(request: &Request) -> bool {
+    return request.get_method() == Method::OPTIONS
+        && request.contains_header("origin")
+        && request.contains_header("access-control-request-method");
+}
+
+/// Returns whether `origin` matches `pattern`, where `*` matches any run of
+/// characters.
+fn matches(pattern: &str, origin: &str) -> bool {
+    return match pattern.split_once('*') {
+        None => pattern.eq_ignore_ascii_case(origin),
+        Some((prefix, rest)) => {
+            origin
+                .get(..prefix.len())
+                .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+                && (prefix.len()..=origin.len())
+                    .filter(|index| origin.is_char_boundary(*index))
+                    .any(|index| matches(rest, &origin[index..]))
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn applied(cors: &Cors, origin: Option<&str>) -> Response {
+        let mut response = Response::new();
+        cors.apply(origin, &mut response);
+        return response;
+    }
+
+    #[test]
+    fn any_allows_every_origin_without_credentials() {
+        for cors in [Cors::any(), Cors::any().credentials(true)] {
+            let response = applied(&cors, Some("https://evil.example"));
+            assert_eq!(
+                response.get_header_str("access-control-allow-origin"),
+                Some("*")
+            );
+            assert!(!response.contains_header("access-control-allow-credentials"));
+            assert!(!response.contains_header("vary"));
+        }
+        let response = applied(
+            &Cors::origins(["*"]).credentials(true),
+            Some("https://evil.example"),
+        );
+        assert!(!response.contains_header("access-control-allow-credentials"));
+    }
+
+    #[test]
+    fn origins_are_echoed_with_credentials_and_vary() {
+        let cors =
+            Cors::origins(["https://example.com", "https://*.example.com"]).credentials(true);
+        assert_eq!(
+            cors.allow_origin(Some("https://docs.example.com"))
+                .as_deref(),
+            Some("https://docs.example.com")
+        );
+        assert_eq!(
+            cors.allow_origin(Some("https://example.com.evil.example")),
+            None
+        );
+        assert_eq!(cors.allow_origin(None), None);
+
+        let response = applied(&cors, Some("https://example.com"));
+        assert_eq!(
+            response.get_header_str("access-control-allow-origin"),
+            Some("https://example.com")
+        );
+        assert_eq!(
+            response.get_header_str("access-control-allow-credentials"),
+            Some("true")
+        );
+        assert_eq!(
+            response.get_header_str("access-control-expose-headers"),
+            Some("Content-Range, ETag")
+        );
+        assert_eq!(response.get_header_str("vary"), Some("Origin"));
+
+        let response = applied(&cors, Some("https://evil.example"));
+        assert!(!response.contains_header("access-control-allow-origin"));
+        assert!(!response.contains_header("access-control-allow-credentials"));
+        assert_eq!(response.get_header_str("vary"), Some("Origin"));
+    }
+
+    #[test]
+    fn preflights_are_answered_for_allowed_origins() {
+        let request = Request::new(Method::OPTIONS, "https://files.example.com/data.json")
+            .with_header("origin", "https://example.com")
+            .with_header("access-control-request-method", "GET")
+            .with_header("access-control-request-headers", "range");
+        assert!(is_preflight(&request));
+        let response = Cors::origins(["https://example.com"])
+            .max_age(600)
+            .preflight(&request);
+        assert_eq!(response.get_status(), 204);
+        assert_eq!(
+            response.get_header_str("access-control-allow-origin"),
+            Some("https://example.com")
+        );
+        assert_eq!(
+            response.get_header_str("access-control-allow-headers"),
+            Some("range")
+        );
+        assert_eq!(
+            response.get_header_str("access-control-max-age"),
+            Some("600")
+        );
+
+        let response = Cors::origins(["https://other.example"]).preflight(&request);
+        assert!(!response.contains_header("access-control-allow-origin"));
+    }
+}
@@ -0,0 +1,9 @@
+use crate::layers::Layers;
+use compute_file_server_metadata::{reserved_key, DEPLOYMENT_KEY};
+use fastly::Error;
+
+pub(crate) fn lookup_deployment(layers: &Layers, prefix: &str) -> Result<Option<String>, Error> {
+    return Ok(layers
+        .lookup(&reserved_key(DEPLOYMENT_KEY, prefix))?
+        .map(|deployment| deployment.into_string()));
+}
@@ -0,0 +1,60 @@
+use compute_file_server_metadata::{metadata_key, Metadata};
+use fastly::{Body, Error, ObjectStore};
+
+/// The header which names the store a response was served from, when
+/// [`FileServer::debug`](crate::FileServer::debug) is enabled.
+pub(crate) const LAYER_HEADER: &str = "x-file-server-layer";
+
+/// One of the stores a `FileServer` serves from.
+pub(crate) struct Layer {
+    pub(crate) name: String,
+    pub(crate) store: ObjectStore,
+}
+
+/// The stores a `FileServer` serves from, in order of precedence.
+pub(crate) struct Layers(Vec<Layer>);
+
+impl Layers {
+    pub(crate) fn open(store_names: &[String]) -> Result<Layers, Error> {
+        let mut layers = Vec::with_capacity(store_names.len());
+        for name in store_names {
+            // Names can come from the sites table at request time, so a
+            // missing store is an error rather than a panic.
+            let store = match ObjectStore::open(name)? {
+                Some(store) => store,
+                None => return Err(Error::msg(format!("ObjectStore `{}` does not exist", name))),
+            };
+            layers.push(Layer {
+                name: name.to_string(),
+                store,
+            });
+        }
+        return Ok(Layers(layers));
+    }
+
+    /// Returns the value stored under `key` in the first layer which has it.
+    pub(crate) fn lookup(&self, key: &str) -> Result<Option<Body>, Error> {
+        for layer in &self.0 {
+            if let Some(body) = layer.store.lookup(key)? {
+                return Ok(Some(body));
+            }
+        }
+        return Ok(None);
+    }
+
+    /// Returns the metadata of the file stored under `key` in the first
+    /// layer which has it, along with that layer, which the file itself must
+    /// then be read from.
+    pub(crate) fn lookup_metadata(&self, key: &str) -> Result<Option<(&Layer, Metadata)>, Error> {
+        let metadata_key = metadata_key(key);
+        for layer in &self.0 {
+            if let Some(metadata) = layer.store.lookup(&metadata_key)? {
+                return Ok(Some((
+                    layer,
+                    serde_json::from_str(&metadata.into_string())?,
+                )));
+            }
+        }
+        return Ok(None);
+    }
+}
@@ -1,69 +1,1129 @@
+#![allow(clippy::needless_return)]
+
+use compute_file_server_metadata::{
+    preview_prefix, reserved_key, surrogate_key, Metadata, RedirectTable, REDIRECTS_KEY,
+};
 use fastly::{http::Method, Body, Error, ObjectStore, Request, Response};
 use http::HeaderMap;
 use http_range::HttpRange;
-use serde_derive::Deserialize;
-use serde_derive::Serialize;
-use serde_json;
+use layers::{Layer, Layers, LAYER_HEADER};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+mod access;
+mod canary;
+mod cors;
+mod deployment;
+mod layers;
+mod manifest;
+mod previews;
+mod security;
+mod signed;
+mod sites;
+
+pub use canary::Canary;
+pub use compute_file_server_metadata::{Asset, AssetManifest};
+pub use cors::Cors;
+pub use manifest::asset_manifest;
+pub use previews::Previews;
+pub use security::SecurityHeaders;
+pub use signed::{verify_signature, SigningKey};
+pub use sites::Sites;
 
-#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct Metadata {
-    #[serde(rename = "ETag")]
-    etag: String,
-    #[serde(rename = "Last-Modified")]
-    last_modified: String,
-    #[serde(rename = "Content-Type")]
-    content_type: Option<String>,
+/// The candidates tried when no `try_files` list has been configured.
+///
+/// This serves `/about/index.html` for both `/about` and `/about/`, and any
+/// path which names a file exactly, such as `/docs/v1.2/intro`.
+const DEFAULT_TRY_FILES: [&str; 2] = ["$uri", "$uri/index.html"];
+
+/// How requests which are served by a directory index, such as
+/// `/docs/index.html`, are canonicalised.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TrailingSlash {
+    /// Serve the directory index for both `/docs` and `/docs/`.
+    #[default]
+    Ignore,
+    /// Redirect `/docs` to `/docs/`.
+    Always,
+    /// Redirect `/docs/` to `/docs`.
+    Never,
 }
 
-pub fn get(store_name: &str, request: Request) -> Result<Option<Response>, Error> {
-    let method = request.get_method();
-    let is_head_request = method == Method::HEAD;
-    // static files should only respond on HEAD and GET requests
-    if !is_head_request && method != Method::GET {
-        return Ok(None);
+/// Serves files which were uploaded to a Fastly Object Store by the
+/// `compute-file-server` CLI.
+///
+/// ```no_run
+/// use compute_file_server::FileServer;
+/// # let request = fastly::Request::get("http://example.com/about");
+/// let server = FileServer::new("site").try_files([
+///     "$uri",
+///     "$uri.html",
+///     "$uri/index.html",
+///     "$uri/index.htm",
+///     "/404.html",
+/// ]);
+/// let response = server.get(request);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileServer {
+    store_names: Vec<String>,
+    try_files: Vec<String>,
+    trailing_slash: TrailingSlash,
+    clean_urls: bool,
+    redirect_status: u16,
+    spa_fallback: Option<String>,
+    error_pages: BTreeMap<u16, String>,
+    redirects: bool,
+    access_rules: bool,
+    security_headers: Option<SecurityHeaders>,
+    cors: Option<Cors>,
+    signing_key: Option<SigningKey>,
+    mount: Option<String>,
+    key_prefix: String,
+    debug: bool,
+    sites: Option<Sites>,
+    previews: Option<Previews>,
+    canary: Option<Canary>,
+    deployment_header: Option<String>,
+}
+
+/// A `try_files` candidate which exists in the store.
+struct Resolved<'a> {
+    key: String,
+    metadata: Metadata,
+    /// The `try_files` template which produced `key`.
+    template: String,
+    /// The store which `key` was found in.
+    layer: &'a Layer,
+}
+
+/// A response for a file in the store, along with the key of that file.
+#[derive(Debug)]
+pub struct Served {
+    /// The key of the file which was served, such as `/about/index.html`.
+    pub key: String,
+    pub response: Response,
+}
+
+impl FileServer {
+    pub fn new(store_name: &str) -> Self {
+        FileServer::layered([store_name])
+    }
+
+    /// Serves files from several stores, such as a per-product store
+    /// followed by a shared design system store. Each file, and each
+    /// `_redirects` or `_access.json` table, is served from the first store
+    /// which has it.
+    pub fn layered<I, S>(store_names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        FileServer {
+            store_names: store_names.into_iter().map(Into::into).collect(),
+            try_files: DEFAULT_TRY_FILES.iter().map(|c| c.to_string()).collect(),
+            trailing_slash: TrailingSlash::Ignore,
+            clean_urls: false,
+            redirect_status: 301,
+            spa_fallback: None,
+            error_pages: BTreeMap::new(),
+            redirects: true,
+            access_rules: true,
+            security_headers: None,
+            cors: None,
+            signing_key: None,
+            mount: None,
+            key_prefix: String::new(),
+            debug: false,
+            sites: None,
+            previews: None,
+            canary: None,
+            deployment_header: None,
+        }
     }
 
-    // if path ends in / or does not have an extension
-    // then append /index.html to the end so we can serve a page
-    let mut path = request.get_path().to_string();
-    if path.ends_with('/') {
-        path += "index.html"
-    } else if !path.contains('.') {
-        path += "/index.html"
+    /// Sets the ordered list of keys which are tried for a request, in the
+    /// style of nginx's `try_files` directive. The first candidate which
+    /// exists in the store is served.
+    ///
+    /// `$uri` is replaced with the path of the request, so `$uri.html` serves
+    /// `/about.html` for `/about`. A candidate without `$uri`, such as
+    /// `/404.html`, is served as-is and is useful as a final fallback.
+    pub fn try_files<I, S>(mut self, candidates: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.try_files = candidates.into_iter().map(Into::into).collect();
+        self
     }
 
-    let metadata_path = format!("{}__metadata__", path);
+    /// Redirects requests for a directory index to the form with, or
+    /// without, a trailing slash.
+    ///
+    /// A directory index is any file served by a `try_files` candidate
+    /// starting with `$uri/`, such as `$uri/index.html`.
+    pub fn trailing_slash(mut self, trailing_slash: TrailingSlash) -> Self {
+        self.trailing_slash = trailing_slash;
+        self
+    }
 
-    let store = ObjectStore::open(store_name).map(|store| store.expect("ObjectStore exists"))?;
+    /// Redirects explicit requests for `/page.html` to `/page`, and for
+    /// `/page/index.html` to `/page/` (or `/page` when combined with
+    /// [`TrailingSlash::Never`]).
+    ///
+    /// The clean URL must resolve back to the same file, so `try_files`
+    /// should contain `$uri.html` when enabling this.
+    pub fn clean_urls(mut self, clean_urls: bool) -> Self {
+        self.clean_urls = clean_urls;
+        self
+    }
+
+    /// Sets the status code used for canonical redirects, defaulting to 301.
+    pub fn redirect_status(mut self, status: u16) -> Self {
+        self.redirect_status = status;
+        self
+    }
 
-    let metadata = store.lookup(&metadata_path)?;
-    if metadata.is_none() {
+    /// Serves `key`, such as `/index.html`, for navigation requests which
+    /// match no file so that a single-page application can handle the route
+    /// on the client.
+    ///
+    /// A navigation request is a GET (or HEAD) request which accepts
+    /// `text/html` and whose final path segment has no file extension, so
+    /// missing assets such as `/app.js` still return `None`.
+    pub fn spa_fallback(mut self, key: &str) -> Self {
+        self.spa_fallback = Some(key.to_string());
+        self
+    }
+
+    /// Sets the document served with `status`, such as `404.html` for 404.
+    ///
+    /// A document without a leading `/` is looked up in the directory of the
+    /// request and then in each parent directory, so `/docs/404.html` is
+    /// served for `/docs/missing` and `/404.html` for `/missing`. A document
+    /// with a leading `/` is always served from that key.
+    ///
+    /// When a 404 document is set, `get` returns it instead of `None` for
+    /// requests which match no file. Other documents, such as for 410 or 500,
+    /// are served via [`FileServer::error_response`].
+    pub fn error_page(mut self, status: u16, document: &str) -> Self {
+        self.error_pages.insert(status, document.to_string());
+        self
+    }
+
+    /// Sets whether the rules uploaded from a `_redirects` file are applied,
+    /// which they are by default.
+    ///
+    /// Rules are evaluated before the request is resolved to a file. A rule
+    /// only applies when no file exists at the requested path, unless its
+    /// status is marked with a `!` to force it. Disabling this saves a
+    /// lookup per request for sites without a `_redirects` file.
+    pub fn redirects(mut self, redirects: bool) -> Self {
+        self.redirects = redirects;
+        self
+    }
+
+    /// Sets whether the rules uploaded from an `_access.json` file are
+    /// enforced, which they are by default.
+    ///
+    /// Rules are checked before the request is resolved to a file, and
+    /// requests without valid credentials for a protected prefix receive a
+    /// 401 with a `WWW-Authenticate` challenge, or the 401 error page if one
+    /// is set. Disabling this saves a lookup per request for sites without
+    /// an `_access.json` file.
+    pub fn access_rules(mut self, access_rules: bool) -> Self {
+        self.access_rules = access_rules;
+        self
+    }
+
+    /// Adds `headers`, such as [`SecurityHeaders::default()`], to every
+    /// response, including 304s and error documents.
+    ///
+    /// Headers set for a path by the `_headers` file take precedence, so the
+    /// preset can be overridden for individual paths.
+    pub fn security_headers(mut self, headers: SecurityHeaders) -> Self {
+        self.security_headers = Some(headers);
+        self
+    }
+
+    /// Applies a CORS policy to every response, and answers preflight
+    /// `OPTIONS` requests for paths which would be served.
+    pub fn cors(mut self, cors: Cors) -> Self {
+        self.cors = Some(cors);
+        self
+    }
+
+    /// Sets the key which verifies signed URLs, such as those produced by
+    /// the CLI's `sign` command.
+    ///
+    /// Files uploaded with `--private` are only served for requests whose
+    /// `expires` and `sig` query parameters are valid for their path, and
+    /// are otherwise rejected with a 403. They are always rejected when no
+    /// key has been set.
+    pub fn signing_key(mut self, key: SigningKey) -> Self {
+        self.signing_key = Some(key);
+        self
+    }
+
+    /// Serves the store under `prefix`, such as `/static`, of a larger
+    /// application.
+    ///
+    /// The prefix is removed from the path of each request before it is
+    /// resolved, so `try_files`, `_redirects` rules and error pages are
+    /// relative to the mount, and requests for paths outside of it return
+    /// `None`. Locations which the library generates, such as for canonical
+    /// redirects, are prefixed again.
+    ///
+    /// Files are looked up under the same prefix, which is where `upload
+    /// --prefix /static` puts them. Call [`FileServer::key_prefix`]
+    /// afterwards to look them up elsewhere.
+    pub fn mount(mut self, prefix: &str) -> Self {
+        let prefix = prefix.trim_end_matches('/').to_string();
+        self.key_prefix = prefix.clone();
+        self.mount = Some(prefix).filter(|prefix| !prefix.is_empty());
+        self
+    }
+
+    /// Looks up files under `prefix`, so that `/about.html` is served from
+    /// the key `/blog/about.html` for `/blog`. Use `""` for files uploaded
+    /// without `--prefix`.
+    pub fn key_prefix(mut self, prefix: &str) -> Self {
+        self.key_prefix = prefix.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Adds an `X-File-Server-Layer` header naming the store which each file
+    /// was served from, for debugging layered stores.
+    pub fn debug(mut self, debug: bool) -> Self {
+        self.debug = debug;
+        self
+    }
+
+    /// Adds a header, such as `X-Deployment`, with the id of the deployment
+    /// being served to every response.
+    pub fn deployment_header(mut self, name: &str) -> Self {
+        self.deployment_header = Some(name.to_string());
+        self
+    }
+
+    /// Returns the id of the latest deployment uploaded by the CLI, or
+    /// `None` if the store predates deployment history.
+    ///
+    /// Sites, previews and canaries each have their own deployments, so
+    /// this is the deployment of the server which [`FileServer::serve`]
+    /// recursed into, rather than of the server it was called on.
+    pub fn deployment(&self) -> Result<Option<String>, Error> {
+        let layers = Layers::open(&self.store_names)?;
+        return deployment::lookup_deployment(&layers, &self.key_prefix);
+    }
+
+    /// Reads the asset manifest uploaded with `--store-manifest` under the
+    /// key prefix, from the first store which has one, returning `None` if
+    /// there is none.
+    pub fn asset_manifest(&self) -> Result<Option<AssetManifest>, Error> {
+        let layers = Layers::open(&self.store_names)?;
+        return manifest::lookup_asset_manifest(&layers, &self.key_prefix);
+    }
+
+    /// Serves a different site for each hostname, according to `sites`.
+    ///
+    /// A site is either a prefix of the store, which replaces any
+    /// [`FileServer::key_prefix`], or a store of its own, which replaces the
+    /// stores given to the constructor. Requests for a hostname without a
+    /// site, and with no `*` default, return `None`.
+    pub fn sites(mut self, sites: Sites) -> Self {
+        self.sites = Some(sites);
+        self
+    }
+
+    /// Returns the server for the site which the host of `request` maps to,
+    /// or `None` if it maps to no site.
+    fn for_site(&self, sites: &Sites, request: &Request) -> Result<Option<FileServer>, Error> {
+        let layers = Layers::open(&self.store_names)?;
+        let site = match sites.find(&layers, request)? {
+            Some(site) => site,
+            None => return Ok(None),
+        };
+        let mut server = self.clone();
+        server.sites = None;
+        if let Some(prefix) = site.prefix {
+            server.key_prefix = prefix.trim_end_matches('/').to_string();
+        }
+        if let Some(store) = site.store {
+            server.store_names = vec![store];
+        }
+        return Ok(Some(server));
+    }
+
+    /// Serves the preview deployments uploaded with `upload --preview
+    /// <branch>` to the requests which `previews` picks them for.
+    ///
+    /// Previews are served from the same store as production, and their
+    /// responses carry `X-Robots-Tag: noindex` so that they are not indexed
+    /// by search engines. Every response, from production or a preview,
+    /// varies on the header and cookie which pick the preview.
+    pub fn previews(mut self, previews: Previews) -> Self {
+        self.previews = Some(previews);
+        self
+    }
+
+    /// Returns the server for the preview which `request` is for, or for
+    /// production, along with whether it is a preview.
+    fn for_preview(&self, previews: &Previews, request: &Request) -> (FileServer, bool) {
+        let mut server = self.clone();
+        server.previews = None;
+        let slug = match previews.find(request) {
+            Some(slug) => slug,
+            None => return (server, false),
+        };
+        server.key_prefix = format!("{}{}", preview_prefix(&slug), self.key_prefix);
+        return (server, true);
+    }
+
+    /// Serves the preview chosen by the CLI's `canary set` command, instead
+    /// of production, to the share of visitors it sets.
+    ///
+    /// Requests for an explicit preview, as picked by
+    /// [`FileServer::previews`], are never split. While a canary is being
+    /// rolled out, responses are marked `private`, as they depend on the
+    /// visitor's bucket.
+    pub fn canary(mut self, canary: Canary) -> Self {
+        self.canary = Some(canary);
+        self
+    }
+
+    /// Returns the server for production or the canary, whichever the
+    /// visitor who sent `request` is assigned to, along with the
+    /// `Set-Cookie` which keeps them there. Returns `None` when no canary is
+    /// being rolled out.
+    fn for_canary(
+        &self,
+        canary: &Canary,
+        request: &Request,
+    ) -> Result<Option<(FileServer, Option<String>)>, Error> {
+        let layers = Layers::open(&self.store_names)?;
+        let rollout = match canary::lookup_rollout(&layers, &self.key_prefix)? {
+            Some(rollout) => rollout,
+            None => return Ok(None),
+        };
+        let (bucket, set_cookie) = canary.bucket(request)?;
+        let mut server = self.clone();
+        server.canary = None;
+        if bucket < rollout.weight {
+            server.key_prefix = format!("{}{}", preview_prefix(&rollout.preview), self.key_prefix);
+        }
+        return Ok(Some((server, set_cookie)));
+    }
+
+    /// Returns the error document configured for `status`, closest to the
+    /// path of `request`, with that status code.
+    ///
+    /// Error documents are never validated against the request's
+    /// preconditions and are marked as uncacheable, so that neither a 304
+    /// nor a cached 200 can be produced from them.
+    pub fn error_response(
+        &self,
+        status: u16,
+        request: &Request,
+    ) -> Result<Option<Response>, Error> {
+        if let Some(sites) = &self.sites {
+            return match self.for_site(sites, request)? {
+                Some(server) => server.error_response(status, request),
+                None => Ok(None),
+            };
+        }
+        if let Some(previews) = &self.previews {
+            let (server, preview) = self.for_preview(previews, request);
+            return Ok(server.error_response(status, request)?.map(|mut response| {
+                if preview {
+                    response.set_header("x-robots-tag", "noindex");
+                }
+                previews.vary(&mut response);
+                response
+            }));
+        }
+        if let Some(canary) = &self.canary {
+            if let Some((server, set_cookie)) = self.for_canary(canary, request)? {
+                return Ok(server.error_response(status, request)?.map(|mut response| {
+                    finish_canary(&mut response, set_cookie);
+                    response
+                }));
+            }
+        }
+        let request = match self.unmount(request.clone_without_body()) {
+            Some(request) => request,
+            None => return Ok(None),
+        };
+        let layers = Layers::open(&self.store_names)?;
+        let origin = request.get_header_str("origin");
+        return match self.error_document(&layers, status, &request)? {
+            Some(served) => {
+                let served = self.finish(served, origin);
+                Ok(Some(self.tag_deployment(served)?.response))
+            }
+            None => Ok(None),
+        };
+    }
+
+    /// Returns a response for the file matching `request`, or `None` when
+    /// no candidate exists in the store.
+    pub fn get(&self, request: Request) -> Result<Option<Response>, Error> {
+        return Ok(self.serve(request)?.map(|served| served.response));
+    }
+
+    /// The same as [`FileServer::get`] but also reports which key was
+    /// served.
+    pub fn serve(&self, request: Request) -> Result<Option<Served>, Error> {
+        if let Some(sites) = &self.sites {
+            return match self.for_site(sites, &request)? {
+                Some(server) => server.serve(request),
+                None => Ok(None),
+            };
+        }
+        if let Some(previews) = &self.previews {
+            let (server, preview) = self.for_preview(previews, &request);
+            return Ok(server.serve(request)?.map(|mut served| {
+                if preview {
+                    served.response.set_header("x-robots-tag", "noindex");
+                }
+                previews.vary(&mut served.response);
+                served
+            }));
+        }
+        if let Some(canary) = &self.canary {
+            if let Some((server, set_cookie)) = self.for_canary(canary, &request)? {
+                return Ok(server.serve(request)?.map(|mut served| {
+                    finish_canary(&mut served.response, set_cookie);
+                    served
+                }));
+            }
+        }
+        let request = match self.unmount(request) {
+            Some(request) => request,
+            None => return Ok(None),
+        };
+        let origin = request.get_header_str("origin").map(str::to_string);
+        let served = match &self.cors {
+            Some(cors) if cors::is_preflight(&request) => {
+                // A preflight is only answered for paths which a GET would serve.
+                let mut head = request.clone_without_body();
+                head.set_method(Method::HEAD);
+                match self.serve_file(head)? {
+                    Some(served) if served.response.get_status().is_success() => Some(Served {
+                        key: served.key,
+                        response: cors.preflight(&request),
+                    }),
+                    _ => None,
+                }
+            }
+            _ => self.serve_authorized(request)?,
+        };
+        return match served {
+            Some(served) => {
+                let served = self.finish(served, origin.as_deref());
+                Ok(Some(self.tag_deployment(served)?))
+            }
+            None => Ok(None),
+        };
+    }
+
+    /// Adds the header configured by [`FileServer::deployment_header`], if
+    /// any, once the deployment is known.
+    fn tag_deployment(&self, mut served: Served) -> Result<Served, Error> {
+        if let Some(name) = &self.deployment_header {
+            if let Some(deployment) = self.deployment()? {
+                served.response.set_header(name, deployment);
+            }
+        }
+        return Ok(served);
+    }
+
+    /// Collapses repeated slashes in the path of `request` and removes the
+    /// mount prefix, returning `None` if the path is outside of the mount.
+    ///
+    /// Access rules and keys are both matched against this path, so that
+    /// `//internal/` cannot skip the rule for `/internal/`.
+    fn unmount(&self, mut request: Request) -> Option<Request> {
+        let path = normalise_path(request.get_path());
+        let path = match &self.mount {
+            Some(mount) => match path.strip_prefix(mount.as_str()) {
+                Some("") => "/".to_string(),
+                Some(path) if path.starts_with('/') => path.to_string(),
+                _ => return None,
+            },
+            None => path,
+        };
+        request.set_path(&path);
+        return Some(request);
+    }
+
+    /// Returns the key of the file at `path` within the site.
+    fn key(&self, path: &str) -> String {
+        return format!("{}{}", self.key_prefix, path);
+    }
+
+    /// Prefixes the `Location` of a redirect with the mount, and adds the
+    /// security and CORS headers, if enabled, to a response for a request
+    /// from `origin`.
+    fn finish(&self, mut served: Served, origin: Option<&str>) -> Served {
+        if let Some(mount) = &self.mount {
+            let location = served
+                .response
+                .get_header_str(http::header::LOCATION)
+                .filter(|location| location.starts_with('/') && !location.starts_with("//"))
+                .map(|location| format!("{}{}", mount, location));
+            if let Some(location) = location {
+                served.response.set_header(http::header::LOCATION, location);
+            }
+        }
+        if let Some(security_headers) = &self.security_headers {
+            security_headers.apply(&mut served.response);
+        }
+        if let Some(cors) = &self.cors {
+            cors.apply(origin, &mut served.response);
+        }
+        return served;
+    }
+
+    /// Serves `request` if it carries the credentials required by the
+    /// access rule covering its path, if any.
+    ///
+    /// Responses for protected paths are marked `private` so that shared
+    /// caches do not serve them to other visitors.
+    fn serve_authorized(&self, request: Request) -> Result<Option<Served>, Error> {
+        let method = request.get_method();
+        if !self.access_rules || (method != Method::HEAD && method != Method::GET) {
+            return self.serve_file(request);
+        }
+        let layers = Layers::open(&self.store_names)?;
+        let rules = match access::lookup_access_rules(&layers, &self.key_prefix)? {
+            Some(rules) => rules,
+            None => return self.serve_file(request),
+        };
+        let rule = match access::find(&rules, request.get_path()) {
+            Some(rule) => rule,
+            None => return self.serve_file(request),
+        };
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs();
+        if let Some(challenge) = access::challenge(rule, &request, now) {
+            let mut served = match self.error_document(&layers, 401, &request)? {
+                Some(served) => served,
+                None => Served {
+                    key: request.get_path().to_string(),
+                    response: Response::from_status(401)
+                        .with_header(http::header::CACHE_CONTROL, "no-store"),
+                },
+            };
+            served
+                .response
+                .set_header(http::header::WWW_AUTHENTICATE, challenge);
+            return Ok(Some(served));
+        }
+        return Ok(self.serve_file(request)?.map(|mut served| {
+            make_private(&mut served.response);
+            served
+        }));
+    }
+
+    fn serve_file(&self, request: Request) -> Result<Option<Served>, Error> {
+        let method = request.get_method();
+        // static files should only respond on HEAD and GET requests
+        if method != Method::HEAD && method != Method::GET {
+            return Ok(None);
+        }
+
+        let layers = Layers::open(&self.store_names)?;
+
+        let path = request.get_path();
+        let redirect_table = match self.redirects {
+            true => lookup_redirects(&layers, &self.key_prefix)?,
+            false => None,
+        };
+        let server = self.with_site_settings(redirect_table.as_ref());
+        let redirect = redirect_table.as_ref().and_then(|table| table.find(path));
+        if let Some(redirect) = &redirect {
+            if redirect.rule.force {
+                return server.apply_redirect(
+                    &layers,
+                    redirect.rule.status,
+                    &redirect.location,
+                    request,
+                );
+            }
+        }
+        let resolved = match server.resolve(&layers, path)? {
+            Some(resolved) => resolved,
+            None => match &redirect {
+                Some(redirect) => {
+                    return server.apply_redirect(
+                        &layers,
+                        redirect.rule.status,
+                        &redirect.location,
+                        request,
+                    )
+                }
+                None => return server.not_found(&layers, request),
+            },
+        };
+
+        let canonical = server.canonical_path(path, &resolved.template);
+        if canonical != path {
+            let location = match request.get_query_str() {
+                Some(query) => format!("{}?{}", canonical, query),
+                None => canonical,
+            };
+            let response = Response::from_status(server.redirect_status)
+                .with_header(http::header::LOCATION, location);
+            return Ok(Some(Served {
+                key: resolved.key,
+                response,
+            }));
+        }
+
+        return self.serve_resolved(&layers, resolved, request);
+    }
+
+    /// Serves the file stored under `key`, unless it is private and the
+    /// request is not signed.
+    fn serve_resolved(
+        &self,
+        layers: &Layers,
+        resolved: Resolved,
+        request: Request,
+    ) -> Result<Option<Served>, Error> {
+        let Resolved {
+            key,
+            metadata,
+            layer,
+            ..
+        } = resolved;
+        if metadata.private {
+            let signed = match &self.signing_key {
+                Some(signing_key) => {
+                    // The signature covers the path which was requested,
+                    // including the mount.
+                    let path = format!(
+                        "{}{}",
+                        self.mount.as_deref().unwrap_or(""),
+                        request.get_path()
+                    );
+                    signing_key.verify(&path, request.get_query_str())?
+                }
+                None => false,
+            };
+            if !signed {
+                if let Some(served) = self.error_document(layers, 403, &request)? {
+                    return Ok(Some(served));
+                }
+                let response =
+                    Response::from_status(403).with_header(http::header::CACHE_CONTROL, "no-store");
+                return Ok(Some(Served { key, response }));
+            }
+        }
+        let response = file_response(&layer.store, &key, &metadata, request)?;
+        return Ok(response.map(|mut response| {
+            // A shared cache must not keep serving a signed URL after it
+            // expires.
+            if metadata.private {
+                make_private(&mut response);
+            }
+            self.tag_layer(layer, &mut response);
+            Served { key, response }
+        }));
+    }
+
+    /// Applies the `trailingSlash` and `cleanUrls` settings which were
+    /// imported alongside the redirect table, unless they have been set in
+    /// code.
+    fn with_site_settings(&self, table: Option<&RedirectTable>) -> Cow<'_, FileServer> {
+        let table = match table {
+            Some(table) if table.trailing_slash.is_some() || table.clean_urls => table,
+            _ => return Cow::Borrowed(self),
+        };
+        let mut server = self.clone();
+        if server.trailing_slash == TrailingSlash::Ignore {
+            server.trailing_slash = match table.trailing_slash {
+                Some(true) => TrailingSlash::Always,
+                Some(false) => TrailingSlash::Never,
+                None => TrailingSlash::Ignore,
+            };
+        }
+        if table.clean_urls && !server.clean_urls {
+            server.clean_urls = true;
+            if !server
+                .try_files
+                .iter()
+                .any(|template| template == "$uri.html")
+            {
+                let index = server
+                    .try_files
+                    .iter()
+                    .position(|template| template == "$uri")
+                    .map(|index| index + 1)
+                    .unwrap_or(0);
+                server.try_files.insert(index, "$uri.html".to_string());
+            }
+        }
+        return Cow::Owned(server);
+    }
+
+    /// Responds to a request which matched a `_redirects` rule.
+    ///
+    /// A 200 serves the file at `location` in place of the requested path, a
+    /// 3xx redirects to `location`, and any other status serves the document
+    /// at `location` with that status, such as for a custom 404 or 410.
+    fn apply_redirect(
+        &self,
+        layers: &Layers,
+        status: u16,
+        location: &str,
+        request: Request,
+    ) -> Result<Option<Served>, Error> {
+        let path = location.split('?').next().unwrap_or(location);
+        if status == 200 {
+            return match self.resolve(layers, path)? {
+                Some(resolved) => self.serve_resolved(layers, resolved, request),
+                None => self.not_found(layers, request),
+            };
+        }
+        if (300..400).contains(&status) {
+            let location = redirect_location(location, request.get_query_str());
+            let response =
+                Response::from_status(status).with_header(http::header::LOCATION, location);
+            return Ok(Some(Served {
+                key: path.to_string(),
+                response,
+            }));
+        }
+        return self.document_response(layers, status, &self.key(path), &request);
+    }
+
+    /// Serves a request which matched no file with the SPA fallback, the
+    /// 404 document, or `None` when neither applies.
+    fn not_found(&self, layers: &Layers, request: Request) -> Result<Option<Served>, Error> {
+        if let Some(fallback) = &self.spa_fallback {
+            if is_navigation_request(&request) {
+                let key = self.key(fallback);
+                if let Some((layer, metadata)) = layers.lookup_metadata(&key)? {
+                    let resolved = Resolved {
+                        key,
+                        metadata,
+                        template: fallback.to_string(),
+                        layer,
+                    };
+                    return self.serve_resolved(layers, resolved, request);
+                }
+            }
+        }
+        return self.error_document(layers, 404, &request);
+    }
+
+    fn error_document(
+        &self,
+        layers: &Layers,
+        status: u16,
+        request: &Request,
+    ) -> Result<Option<Served>, Error> {
+        let document = match self.error_pages.get(&status) {
+            Some(document) => document,
+            None => return Ok(None),
+        };
+        let candidates = if document.starts_with('/') {
+            vec![document.to_string()]
+        } else {
+            ancestor_keys(request.get_path(), document)
+        };
+        for path in candidates {
+            if let Some(served) =
+                self.document_response(layers, status, &self.key(&path), request)?
+            {
+                return Ok(Some(served));
+            }
+        }
         return Ok(None);
     }
-    let metadata = metadata.expect("Metadata is valid");
-    let metadata: Metadata = serde_json::from_str(&metadata.into_string())?;
-    let response = check_preconditions(request, &metadata)?;
+
+    /// Returns the file at `key` with `status`, without evaluating
+    /// preconditions and marked as uncacheable, for error documents.
+    fn document_response(
+        &self,
+        layers: &Layers,
+        status: u16,
+        key: &str,
+        request: &Request,
+    ) -> Result<Option<Served>, Error> {
+        let (layer, metadata) = match layers.lookup_metadata(key)? {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        let item = match layer.store.lookup(key)? {
+            Some(item) => item,
+            None => return Ok(None),
+        };
+        let mut response =
+            Response::from_status(status).with_header(http::header::CACHE_CONTROL, "no-store");
+        if let Some(content_type) = metadata.content_type {
+            response.set_header(http::header::CONTENT_TYPE, content_type);
+        }
+        if request.get_method() != Method::HEAD {
+            response.set_body(item);
+        }
+        self.tag_layer(layer, &mut response);
+        return Ok(Some(Served {
+            key: key.to_string(),
+            response,
+        }));
+    }
+
+    /// Names the store which served `response`, when debugging.
+    fn tag_layer(&self, layer: &Layer, response: &mut Response) {
+        if self.debug {
+            response.set_header(LAYER_HEADER, &layer.name);
+        }
+    }
+
+    /// Returns the first `try_files` candidate for `path` which exists in
+    /// the store.
+    fn resolve<'a>(&self, layers: &'a Layers, path: &str) -> Result<Option<Resolved<'a>>, Error> {
+        for (template, key) in self.candidate_keys(path) {
+            if let Some((layer, metadata)) = layers.lookup_metadata(&key)? {
+                return Ok(Some(Resolved {
+                    key,
+                    metadata,
+                    template: template.to_string(),
+                    layer,
+                }));
+            }
+        }
+        return Ok(None);
+    }
+
+    /// Returns each `try_files` candidate for `path` with the key it names,
+    /// in the order they are tried.
+    fn candidate_keys(&self, path: &str) -> Vec<(&str, String)> {
+        return self
+            .try_files
+            .iter()
+            .filter_map(|template| {
+                let path = expand_candidate(template, path)?;
+                return Some((template.as_str(), self.key(&path)));
+            })
+            .collect();
+    }
+
+    /// Returns the path which should be used to request the file that
+    /// `template` resolved `path` to, according to the `trailing_slash` and
+    /// `clean_urls` options.
+    fn canonical_path(&self, path: &str, template: &str) -> String {
+        let mut directory = template.starts_with("$uri/");
+        let mut canonical = path.to_string();
+        if self.clean_urls && template == "$uri" {
+            if let Some(parent) = path.strip_suffix("/index.html") {
+                canonical = format!("{}/", parent);
+                directory = true;
+            } else if let Some(page) = path.strip_suffix(".html") {
+                canonical = page.to_string();
+            }
+        }
+        if directory {
+            match self.trailing_slash {
+                TrailingSlash::Ignore => {}
+                TrailingSlash::Always => {
+                    if !canonical.ends_with('/') {
+                        canonical.push('/');
+                    }
+                }
+                TrailingSlash::Never => {
+                    if canonical.len() > 1 && canonical.ends_with('/') {
+                        canonical.pop();
+                    }
+                }
+            }
+        }
+        return canonical;
+    }
+}
+
+fn lookup_redirects(layers: &Layers, prefix: &str) -> Result<Option<RedirectTable>, Error> {
+    return match layers.lookup(&reserved_key(REDIRECTS_KEY, prefix))? {
+        Some(table) => Ok(Some(serde_json::from_str(&table.into_string())?)),
+        None => Ok(None),
+    };
+}
+
+/// Stops shared caches, including Fastly's, from storing a response which
+/// required credentials or a signature.
+fn make_private(response: &mut Response) {
+    let cache_control = private_cache_control(response.get_header_str(http::header::CACHE_CONTROL));
+    response.set_header(http::header::CACHE_CONTROL, cache_control);
+    response.remove_header("surrogate-control");
+}
+
+/// Returns where a request with `query` is redirected to by a rule whose
+/// destination is `location`. The query is kept unless the destination has
+/// one of its own.
+fn redirect_location(location: &str, query: Option<&str>) -> String {
+    return match query {
+        Some(query) if !location.contains('?') => format!("{}?{}", location, query),
+        _ => location.to_string(),
+    };
+}
+
+/// Keeps a response which depends on the visitor's canary bucket out of
+/// shared caches, which would otherwise serve one bucket's deployment to
+/// every visitor, and assigns the bucket if the visitor had none.
+fn finish_canary(response: &mut Response, set_cookie: Option<String>) {
+    make_private(response);
+    if let Some(set_cookie) = set_cookie {
+        response.append_header(http::header::SET_COOKIE, set_cookie);
+    }
+}
+
+/// Returns `cache_control` with `public` and `s-maxage` replaced by
+/// `private`, for responses which required credentials.
+fn private_cache_control(cache_control: Option<&str>) -> String {
+    let mut directives = vec!["private"];
+    for directive in split_list(cache_control.unwrap_or("")) {
+        let name = directive.split('=').next().unwrap_or("").trim();
+        if !name.is_empty()
+            && !["public", "private", "s-maxage"]
+                .iter()
+                .any(|excluded| name.eq_ignore_ascii_case(excluded))
+        {
+            directives.push(directive);
+        }
+    }
+    return directives.join(", ");
+}
+
+/// Returns `document` within the directory of `path` and each of its
+/// parents, nearest first.
+fn ancestor_keys(path: &str, document: &str) -> Vec<String> {
+    let mut keys = Vec::new();
+    let mut directory = &path[..path.rfind('/').map(|index| index + 1).unwrap_or(0)];
+    loop {
+        keys.push(format!("{}{}", directory, document));
+        match directory.trim_end_matches('/').rfind('/') {
+            Some(index) => directory = &directory[..=index],
+            None => break,
+        }
+    }
+    return keys;
+}
+
+/// Returns `path` with each run of slashes collapsed into one.
+fn normalise_path(path: &str) -> String {
+    let mut normalised = String::with_capacity(path.len());
+    for c in path.chars() {
+        if !(c == '/' && normalised.ends_with('/')) {
+            normalised.push(c);
+        }
+    }
+    return normalised;
+}
+
+/// Adds `name` to the `Vary` header of `response`, keeping any values
+/// already there.
+pub(crate) fn add_vary(response: &mut Response, name: &str) {
+    let vary = response.get_header_str("vary").unwrap_or("").trim();
+    if split_list(vary)
+        .iter()
+        .any(|value| value.eq_ignore_ascii_case(name))
+    {
+        return;
+    }
+    let vary = match vary.is_empty() {
+        true => name.to_string(),
+        false => format!("{}, {}", vary, name),
+    };
+    response.set_header("vary", vary);
+}
+
+/// Returns the value of the cookie `name` sent with `request`.
+pub(crate) fn cookie(request: &Request, name: &str) -> Option<String> {
+    return request
+        .get_header_all_str("cookie")
+        .into_iter()
+        .flat_map(|header| header.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_string());
+}
+
+fn is_navigation_request(request: &Request) -> bool {
+    let accepts_html = request
+        .get_header_str("accept")
+        .map(|accept| accept.contains("text/html"))
+        .unwrap_or(false);
+    let last_segment = request.get_path().rsplit('/').next().unwrap_or("");
+    return accepts_html && !last_segment.contains('.');
+}
+
+/// Replaces `$uri` in `template` with `path`.
+///
+/// Returns `None` for candidates which can never name a file, such as
+/// `/docs/.html` for `$uri.html` when the path is `/docs/`, so that they do
+/// not cost a lookup.
+fn expand_candidate(template: &str, path: &str) -> Option<String> {
+    if path.ends_with('/')
+        && template
+            .match_indices("$uri")
+            .any(|(index, _)| !matches!(template[index + 4..].chars().next(), None | Some('/')))
+    {
+        return None;
+    }
+    // Only the slashes where `path` joins the template are collapsed, as
+    // the path itself has already been normalised.
+    let mut key = String::new();
+    let mut rest = template;
+    while let Some(index) = rest.find("$uri") {
+        key.push_str(&rest[..index]);
+        match key.ends_with('/') {
+            true => key.push_str(path.strip_prefix('/').unwrap_or(path)),
+            false => key.push_str(path),
+        }
+        rest = &rest[index + 4..];
+        if key.ends_with('/') {
+            rest = rest.strip_prefix('/').unwrap_or(rest);
+        }
+    }
+    key.push_str(rest);
+    if key.ends_with('/') {
+        return None;
+    }
+    return Some(key);
+}
+
+pub fn get(store_name: &str, request: Request) -> Result<Option<Response>, Error> {
+    return FileServer::new(store_name).get(request);
+}
+
+fn file_response(
+    store: &ObjectStore,
+    key: &str,
+    metadata: &Metadata,
+    request: Request,
+) -> Result<Option<Response>, Error> {
+    let is_head_request = request.get_method() == Method::HEAD;
+    let response = check_preconditions(request, key, metadata)?;
     if let (Some(response), _) = response {
         return Ok(Some(response));
     }
     let request = response.1;
-    
-    let item = store.lookup(&path)?;
+
+    let item = store.lookup(key)?;
 
     match item {
         None => return Ok(None),
         Some(item) => {
-            let mut headers = HeaderMap::new();
-            headers.insert(http::header::ETAG, metadata.etag.parse()?);
-            headers.insert(http::header::LAST_MODIFIED, metadata.last_modified.parse()?);
+            let mut headers = representation_headers(key, metadata, &request)?;
 
-            headers.insert(http::header::ACCEPT_RANGES, "bytes".parse()?);
-
-            if let Some(content_type) = metadata.content_type {
-                headers.insert(http::header::CONTENT_TYPE, content_type.parse()?);
-            }
             let range = request.get_header_str("range");
+            let wants_content_digest = wants_sha256(request.get_header_str("want-content-digest"));
 
             match range {
                 Some(range) => {
@@ -83,10 +1143,14 @@ pub fn get(store_name: &str, request: Request) -> Result<Option<Response>, Error
                                     http::header::CONTENT_LENGTH,
                                     (end - start + 1).to_string().parse()?,
                                 );
-                                let mut response = Response::from_status(206);
-                                for (name, value) in headers {
-                                    response.set_header(name.expect("name is a HeaderName"), value);
+                                if wants_content_digest {
+                                    headers.insert(
+                                        "content-digest",
+                                        content_digest(&item_buffer[start..end]).parse()?,
+                                    );
                                 }
+                                let mut response = Response::from_status(206);
+                                append_headers(&mut response, &headers);
                                 if is_head_request {
                                     return Ok(Some(response));
                                 } else {
@@ -117,15 +1181,24 @@ pub fn get(store_name: &str, request: Request) -> Result<Option<Response>, Error
                                     body.write_bytes(boundary);
                                     length += boundary.len();
                                     if let Some(ref mime_type) = mime_type {
-                                        body.write_bytes(&mime_type);
+                                        body.write_bytes(mime_type);
                                         length += mime_type.len();
                                     }
-                                    let range = format!("Content-Range: bytes {}-{}/{}\n\n", start, end, total)
-                                        .as_bytes()
-                                        .to_owned();
+                                    let buffer = &item_buffer[start..end];
+                                    let digest = match wants_content_digest {
+                                        true => {
+                                            format!("Content-Digest: {}\n", content_digest(buffer))
+                                        }
+                                        false => String::new(),
+                                    };
+                                    let range = format!(
+                                        "Content-Range: bytes {}-{}/{}\n{}\n",
+                                        start, end, total, digest
+                                    )
+                                    .as_bytes()
+                                    .to_owned();
                                     body.write_bytes(&range);
                                     length += range.len();
-                                    let buffer = &item_buffer[start..end];
                                     body.write_bytes(buffer);
                                     length += buffer.len();
                                 }
@@ -136,9 +1209,7 @@ pub fn get(store_name: &str, request: Request) -> Result<Option<Response>, Error
                                     length.to_string().parse()?,
                                 );
                                 let mut response = Response::from_status(206);
-                                for (name, value) in headers {
-                                    response.set_header(name.expect("name is a HeaderName"), value);
-                                }
+                                append_headers(&mut response, &headers);
                                 if is_head_request {
                                     return Ok(Some(response));
                                 } else {
@@ -160,14 +1231,13 @@ pub fn get(store_name: &str, request: Request) -> Result<Option<Response>, Error
                                 );
                             }
                             http_range::HttpRangeParseError::NoOverlap => {
+                                headers.remove(http::header::CONTENT_LENGTH);
                                 headers.insert(
                                     http::header::CONTENT_RANGE,
                                     format!("bytes */{}", total).parse()?,
                                 );
                                 let mut response = Response::from_status(416);
-                                for (name, value) in headers {
-                                    response.set_header(name.expect("name is a HeaderName"), value);
-                                }
+                                append_headers(&mut response, &headers);
                                 return Ok(Some(response));
                             }
                         },
@@ -187,17 +1257,126 @@ fn non_range_response(
     item: Body,
 ) -> Result<Option<Response>, Error> {
     let mut response = Response::from_status(200);
-    for (name, value) in headers {
-        response.set_header(name.expect("name is a HeaderName"), value)
-    }
+    append_headers(&mut response, &headers);
     if !is_head_request {
         response.set_body(item);
     }
     return Ok(Some(response));
 }
 
+/// Returns the headers which describe the stored file, including any set
+/// for its path by the `_headers` file at upload time.
+fn representation_headers(
+    key: &str,
+    metadata: &Metadata,
+    request: &Request,
+) -> Result<HeaderMap, Error> {
+    let mut headers = HeaderMap::new();
+    if !metadata.etag.is_empty() {
+        headers.insert(http::header::ETAG, metadata.etag.parse()?);
+    }
+    if !metadata.last_modified.is_empty() {
+        headers.insert(http::header::LAST_MODIFIED, metadata.last_modified.parse()?);
+    }
+
+    headers.insert(http::header::ACCEPT_RANGES, "bytes".parse()?);
+
+    if let Some(content_type) = &metadata.content_type {
+        headers.insert(http::header::CONTENT_TYPE, content_type.parse()?);
+    }
+    // The length of the whole file, which range responses replace with the
+    // length of their body.
+    if let Some(content_length) = metadata.content_length {
+        headers.insert(http::header::CONTENT_LENGTH, content_length.into());
+    }
+    if let Some(cache_control) = &metadata.cache_control {
+        headers.insert(http::header::CACHE_CONTROL, cache_control.parse()?);
+    }
+    if let Some(surrogate_control) = &metadata.surrogate_control {
+        headers.insert("surrogate-control", surrogate_control.parse()?);
+    }
+    headers.insert("surrogate-key", surrogate_keys(key, metadata).parse()?);
+    if let Some(sha256) = &metadata.sha256 {
+        if wants_sha256(request.get_header_str("want-repr-digest")) {
+            headers.insert("repr-digest", format!("sha-256=:{}:", sha256).parse()?);
+        }
+    }
+    for (name, values) in &metadata.headers {
+        let name = http::header::HeaderName::from_bytes(name.as_bytes())?;
+        headers.remove(&name);
+        for value in values {
+            headers.append(&name, value.parse()?);
+        }
+    }
+    return Ok(headers);
+}
+
+/// Adds every value of `headers` to `response`, so that repeated headers
+/// such as `Set-Cookie` are each sent.
+fn append_headers(response: &mut Response, headers: &HeaderMap) {
+    for (name, value) in headers {
+        response.append_header(name, value);
+    }
+}
+
+/// Returns the `Surrogate-Key`s of the file stored under `key`, so that it
+/// can be purged by itself, along with every file in one of its directories,
+/// of its content type, or from its deployment.
+///
+/// For `/blog/post.html` from deployment `1666000000000` these are
+/// `/blog/post.html / /blog/ text/html 1666000000000`.
+fn surrogate_keys(key: &str, metadata: &Metadata) -> String {
+    let mut keys = vec![surrogate_key(key)];
+    for (index, _) in key.match_indices('/') {
+        keys.push(surrogate_key(&key[..=index]));
+    }
+    if let Some(content_type) = &metadata.content_type {
+        let essence = content_type.split(';').next().unwrap_or(content_type);
+        keys.push(essence.trim().to_string());
+    }
+    if let Some(deployment) = &metadata.deployment {
+        keys.push(deployment.to_string());
+    }
+    return keys.join(" ");
+}
+
+/// Returns the RFC 9530 `Content-Digest` of a part of a file.
+fn content_digest(content: &[u8]) -> String {
+    return format!("sha-256=:{}:", base64::encode(Sha256::digest(content)));
+}
+
+/// Returns whether a `Want-Repr-Digest` or `Want-Content-Digest` field, if
+/// sent, prefers a SHA-256 digest over none at all, i.e. does not omit it or
+/// give it a weight of 0.
+fn wants_sha256(want: Option<&str>) -> bool {
+    let want = match want {
+        Some(want) => want,
+        None => return true,
+    };
+    for preference in want.split(',') {
+        if let Some((algorithm, weight)) = preference.split_once('=') {
+            if algorithm.trim().eq_ignore_ascii_case("sha-256") {
+                return weight.trim().parse::<u8>().is_ok_and(|weight| weight > 0);
+            }
+        }
+    }
+    return false;
+}
+
+fn not_modified_response(
+    key: &str,
+    metadata: &Metadata,
+    request: &Request,
+) -> Result<Response, Error> {
+    let headers = representation_headers(key, metadata, request)?;
+    let mut response = Response::from_status(304);
+    append_headers(&mut response, &headers);
+    return Ok(response);
+}
+
 fn check_preconditions(
     mut request: Request,
+    key: &str,
     metadata: &Metadata,
 ) -> Result<(Option<Response>, Request), Error> {
     // https://httpwg.org/specs/rfc9110.html#rfc.section.13.2.2
@@ -233,28 +1412,10 @@ fn check_preconditions(
     if let Some(header) = header {
         if !if_none_match(metadata, header.to_str()?) {
             if method == get || method == head {
-                let mut response = Response::from_status(304);
-                response.set_header(
-                    http::header::ETAG,
-                    metadata.etag.parse::<http::HeaderValue>()?,
-                );
-                response.set_header(
-                    http::header::LAST_MODIFIED,
-                    metadata.last_modified.parse::<http::HeaderValue>()?,
-                );
-
-                response.set_header(
-                    http::header::ACCEPT_RANGES,
-                    "bytes".parse::<http::HeaderValue>()?,
-                );
-
-                if let Some(content_type) = &metadata.content_type {
-                    response.set_header(
-                        http::header::CONTENT_TYPE,
-                        content_type.parse::<http::HeaderValue>()?,
-                    );
-                }
-                return Ok((Some(response), request));
+                return Ok((
+                    Some(not_modified_response(key, metadata, &request)?),
+                    request,
+                ));
             }
             return Ok((Some(Response::from_status(412)), request));
         }
@@ -266,28 +1427,10 @@ fn check_preconditions(
             header = request.get_header("if-modified-since");
             if let Some(header) = header {
                 if !if_modified_since(metadata, header.to_str()?) {
-                    let mut response = Response::from_status(304);
-                    response.set_header(
-                        http::header::ETAG,
-                        metadata.etag.parse::<http::HeaderValue>()?,
-                    );
-                    response.set_header(
-                        http::header::LAST_MODIFIED,
-                        metadata.last_modified.parse::<http::HeaderValue>()?,
-                    );
-
-                    response.set_header(
-                        http::header::ACCEPT_RANGES,
-                        "bytes".parse::<http::HeaderValue>()?,
-                    );
-
-                    if let Some(content_type) = &metadata.content_type {
-                        response.set_header(
-                            http::header::CONTENT_TYPE,
-                            content_type.parse::<http::HeaderValue>()?,
-                        );
-                    }
-                    return Ok((Some(response), request));
+                    return Ok((
+                        Some(not_modified_response(key, metadata, &request)?),
+                        request,
+                    ));
                 }
             }
         }
@@ -296,14 +1439,12 @@ fn check_preconditions(
     // 5. When the method is GET and both Range and If-Range are present, evaluate the If-Range precondition:
     // - if true and the Range is applicable to the selected representation, respond 206 (Partial Content)
     // - otherwise, ignore the Range header field and respond 200 (OK)
-    if method == get {
-        if request.contains_header("range") {
-            header = request.get_header("if-range");
-            if let Some(header) = header {
-                if !if_range(metadata, header.to_str()?) {
-                    // We delete the range headers so that the `get` function will return the full body
-                    request.remove_header("range");
-                }
+    if method == get && request.contains_header("range") {
+        header = request.get_header("if-range");
+        if let Some(header) = header {
+            if !if_range(metadata, header.to_str()?) {
+                // We delete the range headers so that the `get` function will return the full body
+                request.remove_header("range");
             }
         }
     }
@@ -340,7 +1481,7 @@ fn strong_match(a: &str, b: &str) -> bool {
 }
 
 fn split_list(value: &str) -> Vec<&str> {
-    return value.split(",").into_iter().map(|s| s.trim()).collect();
+    return value.split(",").map(|s| s.trim()).collect();
 }
 
 // https://httpwg.org/specs/rfc9110.html#field.if-match
@@ -405,8 +1546,14 @@ fn if_modified_since(validation_fields: &Metadata, header: &str) -> bool {
         return true;
     }
 
+    // The condition is true when the representation has no modification date to compare, such as when it was uploaded without one.
+    let last_modified = match httpdate::parse_http_date(&validation_fields.last_modified) {
+        Ok(last_modified) => last_modified,
+        Err(_) => return true,
+    };
+
     // 1. If the selected representation's last modification date is earlier or equal to the date provided in the field value, the condition is false.
-    if httpdate::parse_http_date(&validation_fields.last_modified).expect("validation_fields.last_modified is valid HTTP-date") <= date.expect("date is valid HTTP-date") {
+    if last_modified <= date.expect("date is valid HTTP-date") {
         return false;
     }
     // 2. Otherwise, the condition is true.
@@ -436,7 +1583,7 @@ fn if_range(validation_fields: &Metadata, header: &str) -> bool {
         // To evaluate a received If-Range header field containing an HTTP-date:
         // 1. If the HTTP-date validator provided is not a strong validator in the sense defined by Section 8.8.2.2, the condition is false.
         // 2. If the HTTP-date validator provided exactly matches the Last-Modified field value for the selected representation, the condition is true.
-        if httpdate::parse_http_date(&validation_fields.last_modified).expect("validation_fields.last_modified is valid HTTP-date") == date {
+        if httpdate::parse_http_date(&validation_fields.last_modified).ok() == Some(date) {
             return true;
         }
         // 3. Otherwise, the condition is false.
@@ -451,3 +1598,257 @@ fn if_range(validation_fields: &Metadata, header: &str) -> bool {
         return false;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalise_path_collapses_slashes() {
+        assert_eq!(
+            normalise_path("//internal//secret.html"),
+            "/internal/secret.html"
+        );
+        assert_eq!(normalise_path("/docs/"), "/docs/");
+    }
+
+    #[test]
+    fn expand_candidate_only_collapses_the_join() {
+        assert_eq!(
+            expand_candidate("$uri/index.html", "/docs/").as_deref(),
+            Some("/docs/index.html")
+        );
+        assert_eq!(
+            expand_candidate("/blog/$uri", "/post.html").as_deref(),
+            Some("/blog/post.html")
+        );
+        // An unnormalised path never reaches a key which an access rule
+        // would have protected.
+        assert_eq!(
+            expand_candidate("$uri", "//internal/secret.html").as_deref(),
+            Some("//internal/secret.html")
+        );
+    }
+
+    #[test]
+    fn unmount_strips_the_mount_on_segment_boundaries() {
+        let server = FileServer::new("site").mount("/blog/");
+        let unmount = |path: &str| {
+            return server
+                .unmount(Request::get(format!("https://example.com{}", path)))
+                .map(|request| request.get_path().to_string());
+        };
+        assert_eq!(unmount("/blog").as_deref(), Some("/"));
+        assert_eq!(unmount("/blog/").as_deref(), Some("/"));
+        assert_eq!(unmount("/blog/post").as_deref(), Some("/post"));
+        assert_eq!(unmount("//blog//post").as_deref(), Some("/post"));
+        assert_eq!(unmount("/blogger"), None);
+        assert_eq!(unmount("/"), None);
+
+        let request = server
+            .unmount(Request::get("https://example.com/blog/search?q=rust"))
+            .unwrap();
+        assert_eq!(request.get_path(), "/search");
+        assert_eq!(request.get_query_str(), Some("q=rust"));
+
+        let request = FileServer::new("site")
+            .unmount(Request::get("https://example.com//docs//intro"))
+            .unwrap();
+        assert_eq!(request.get_path(), "/docs/intro");
+    }
+
+    #[test]
+    fn candidate_keys_follow_the_try_files_order() {
+        let server = FileServer::new("site");
+        assert_eq!(
+            server.candidate_keys("/docs"),
+            vec![
+                ("$uri", "/docs".to_string()),
+                ("$uri/index.html", "/docs/index.html".to_string()),
+            ]
+        );
+        let server = FileServer::new("site")
+            .try_files(["$uri.html", "$uri", "$uri/index.html", "/404.html"])
+            .key_prefix("/blog/");
+        assert_eq!(
+            server.candidate_keys("/about"),
+            vec![
+                ("$uri.html", "/blog/about.html".to_string()),
+                ("$uri", "/blog/about".to_string()),
+                ("$uri/index.html", "/blog/about/index.html".to_string()),
+                ("/404.html", "/blog/404.html".to_string()),
+            ]
+        );
+        // `$uri.html` and `$uri` can never name a file for a directory, so
+        // they are skipped rather than costing a lookup.
+        assert_eq!(
+            server.candidate_keys("/docs/"),
+            vec![
+                ("$uri/index.html", "/blog/docs/index.html".to_string()),
+                ("/404.html", "/blog/404.html".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn canonical_path_applies_trailing_slash_to_directory_indexes() {
+        let server = FileServer::new("site");
+        assert_eq!(server.canonical_path("/docs", "$uri/index.html"), "/docs");
+        assert_eq!(server.canonical_path("/docs/", "$uri/index.html"), "/docs/");
+
+        let server = FileServer::new("site").trailing_slash(TrailingSlash::Always);
+        assert_eq!(server.canonical_path("/docs", "$uri/index.html"), "/docs/");
+        assert_eq!(server.canonical_path("/docs/", "$uri/index.html"), "/docs/");
+        assert_eq!(server.canonical_path("/page", "$uri"), "/page");
+
+        let server = FileServer::new("site").trailing_slash(TrailingSlash::Never);
+        assert_eq!(server.canonical_path("/docs/", "$uri/index.html"), "/docs");
+        assert_eq!(server.canonical_path("/", "$uri/index.html"), "/");
+    }
+
+    #[test]
+    fn canonical_path_cleans_urls_which_name_html_files() {
+        let server = FileServer::new("site").clean_urls(true);
+        assert_eq!(server.canonical_path("/about.html", "$uri"), "/about");
+        assert_eq!(server.canonical_path("/about", "$uri.html"), "/about");
+        assert_eq!(server.canonical_path("/docs/index.html", "$uri"), "/docs/");
+        assert_eq!(server.canonical_path("/logo.png", "$uri"), "/logo.png");
+
+        let server = server.trailing_slash(TrailingSlash::Never);
+        assert_eq!(server.canonical_path("/docs/index.html", "$uri"), "/docs");
+        assert_eq!(server.canonical_path("/index.html", "$uri"), "/");
+    }
+
+    #[test]
+    fn navigation_requests_accept_html_for_paths_without_an_extension() {
+        let html = "text/html,application/xhtml+xml,*/*;q=0.8";
+        for path in ["/", "/dashboard", "/docs/", "/v1.2/settings"] {
+            let request =
+                Request::get(format!("https://example.com{}", path)).with_header("accept", html);
+            assert!(is_navigation_request(&request), "{}", path);
+        }
+        let request = Request::get("https://example.com/app.js").with_header("accept", html);
+        assert!(!is_navigation_request(&request));
+        let request =
+            Request::get("https://example.com/dashboard").with_header("accept", "application/json");
+        assert!(!is_navigation_request(&request));
+        assert!(!is_navigation_request(&Request::get(
+            "https://example.com/dashboard"
+        )));
+    }
+
+    #[test]
+    fn ancestor_keys_start_with_the_nearest_directory() {
+        assert_eq!(
+            ancestor_keys("/docs/guide/intro.html", "404.html"),
+            vec!["/docs/guide/404.html", "/docs/404.html", "/404.html"]
+        );
+        assert_eq!(
+            ancestor_keys("/docs/", "404.html"),
+            vec!["/docs/404.html", "/404.html"]
+        );
+        assert_eq!(ancestor_keys("/missing", "404.html"), vec!["/404.html"]);
+        assert_eq!(ancestor_keys("/", "404.html"), vec!["/404.html"]);
+    }
+
+    #[test]
+    fn header_rules_keep_every_value_and_replace_stored_headers() {
+        let metadata = Metadata {
+            cache_control: Some("public, max-age=60".to_string()),
+            headers: BTreeMap::from([
+                ("Cache-Control".to_string(), vec!["no-cache".to_string()]),
+                (
+                    "Set-Cookie".to_string(),
+                    vec!["a=1; Path=/".to_string(), "b=2; Path=/".to_string()],
+                ),
+            ]),
+            ..Metadata::default()
+        };
+        let request = Request::get("https://example.com/index.html");
+        let headers = representation_headers("/index.html", &metadata, &request).unwrap();
+        let mut response = Response::new();
+        append_headers(&mut response, &headers);
+        assert_eq!(
+            response.get_header_all_str("set-cookie"),
+            ["a=1; Path=/", "b=2; Path=/"]
+        );
+        assert_eq!(response.get_header_all_str("cache-control"), ["no-cache"]);
+    }
+
+    #[test]
+    fn surrogate_keys_start_with_the_key_the_cli_purges() {
+        let metadata = Metadata {
+            content_type: Some("text/html; charset=utf-8".to_string()),
+            deployment: Some("1666000000000".to_string()),
+            ..Metadata::default()
+        };
+        let keys = surrogate_keys("/blog/my post.html", &metadata);
+        assert_eq!(
+            keys,
+            "/blog/my%20post.html / /blog/ text/html 1666000000000"
+        );
+        assert_eq!(
+            keys.split(' ').next(),
+            Some(surrogate_key("/blog/my post.html").as_str())
+        );
+    }
+
+    #[test]
+    fn wants_sha256_reads_the_weight_of_sha_256() {
+        for (want, wanted) in [
+            (None, true),
+            (Some("sha-256=1"), true),
+            (Some("sha-512=3, sha-256=10"), true),
+            (Some(" SHA-256 = 5 "), true),
+            (Some("sha-256=0"), false),
+            (Some("sha-512=3"), false),
+            (Some("sha-256"), false),
+            (Some("sha-256=high"), false),
+            (Some(""), false),
+        ] {
+            assert_eq!(wants_sha256(want), wanted, "{:?}", want);
+        }
+    }
+
+    #[test]
+    fn canary_responses_are_kept_out_of_shared_caches() {
+        let mut response = Response::new()
+            .with_header("cache-control", "public, max-age=60, s-maxage=600")
+            .with_header("surrogate-control", "max-age=3600");
+        finish_canary(&mut response, Some("canary=7; Path=/".to_string()));
+        assert_eq!(
+            response.get_header_str("cache-control"),
+            Some("private, max-age=60")
+        );
+        assert!(!response.contains_header("surrogate-control"));
+        assert_eq!(
+            response.get_header_str("set-cookie"),
+            Some("canary=7; Path=/")
+        );
+
+        let mut response = Response::new();
+        finish_canary(&mut response, None);
+        assert_eq!(response.get_header_str("cache-control"), Some("private"));
+        assert!(!response.contains_header("set-cookie"));
+    }
+
+    #[test]
+    fn redirects_keep_the_query_unless_the_rule_sets_one() {
+        assert_eq!(redirect_location("/blog/", Some("page=2")), "/blog/?page=2");
+        assert_eq!(redirect_location("/blog/", None), "/blog/");
+        assert_eq!(
+            redirect_location("/search?q=launch", Some("page=2")),
+            "/search?q=launch"
+        );
+    }
+
+    #[test]
+    fn add_vary_keeps_existing_values_once() {
+        let mut response = Response::new();
+        add_vary(&mut response, "Cookie");
+        assert_eq!(response.get_header_str("vary"), Some("Cookie"));
+        add_vary(&mut response, "Origin");
+        add_vary(&mut response, "cookie");
+        assert_eq!(response.get_header_str("vary"), Some("Cookie, Origin"));
+    }
+}
@@ -0,0 +1,23 @@
+use crate::layers::Layers;
+use crate::FileServer;
+use compute_file_server_metadata::{reserved_key, AssetManifest, MANIFEST_KEY};
+use fastly::Error;
+
+/// Reads the asset manifest from the root of the store named `store_name`,
+/// returning `None` if it was not uploaded with `--store-manifest`.
+///
+/// Use [`FileServer::asset_manifest`] for sites uploaded with `--prefix` or
+/// `--preview`, or served from layered stores.
+pub fn asset_manifest(store_name: &str) -> Result<Option<AssetManifest>, Error> {
+    return FileServer::new(store_name).asset_manifest();
+}
+
+pub(crate) fn lookup_asset_manifest(
+    layers: &Layers,
+    prefix: &str,
+) -> Result<Option<AssetManifest>, Error> {
+    return match layers.lookup(&reserved_key(MANIFEST_KEY, prefix))? {
+        Some(manifest) => Ok(Some(serde_json::from_str(&manifest.into_string())?)),
+        None => Ok(None),
+    };
+}
@@ -0,0 +1,121 @@
+use compute_file_server_metadata::preview_slug;
+use fastly::{Request, Response};
+
+/// How [`FileServer::previews`](crate::FileServer::previews) picks the
+/// preview deployment, uploaded with `upload --preview <branch>`, which a
+/// request is for.
+///
+/// ```no_run
+/// use compute_file_server::{FileServer, Previews};
+/// let server = FileServer::new("site").previews(
+///     Previews::subdomain("*.preview.example.com")
+///         .cookie("preview")
+///         .header("x-preview"),
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Previews {
+    subdomain: Option<String>,
+    cookie: Option<String>,
+    header: Option<String>,
+}
+
+impl Previews {
+    /// Picks the preview from the first label of hosts matching `pattern`,
+    /// such as `*.preview.example.com`, so that
+    /// `feature-login.preview.example.com` serves the `feature/login`
+    /// branch.
+    pub fn subdomain(pattern: &str) -> Self {
+        return Previews {
+            subdomain: Some(pattern.to_ascii_lowercase()),
+            ..Previews::default()
+        };
+    }
+
+    /// Picks the preview named by the cookie `name`.
+    pub fn cookie(mut self, name: &str) -> Self {
+        self.cookie = Some(name.to_string());
+        return self;
+    }
+
+    /// Picks the preview named by the request header `name`, which takes
+    /// precedence over the cookie and subdomain.
+    pub fn header(mut self, name: &str) -> Self {
+        self.header = Some(name.to_string());
+        return self;
+    }
+
+    /// Adds the request headers which choose the preview to `Vary`, so that
+    /// shared caches never serve a preview to production visitors, or
+    /// production to reviewers. Hosts are already part of the cache key.
+    pub(crate) fn vary(&self, response: &mut Response) {
+        if let Some(header) = &self.header {
+            crate::add_vary(response, header);
+        }
+        if self.cookie.is_some() {
+            crate::add_vary(response, "Cookie");
+        }
+    }
+
+    /// Returns the slug of the preview which `request` is for, or `None` if
+    /// it is for production.
+    pub(crate) fn find(&self, request: &Request) -> Option<String> {
+        let header = self
+            .header
+            .as_ref()
+            .and_then(|name| request.get_header_str(name.as_str()))
+            .map(str::to_string);
+        let cookie = || {
+            let name = self.cookie.as_deref()?;
+            return crate::cookie(request, name);
+        };
+        let subdomain = || {
+            let suffix = self.subdomain.as_deref()?.strip_prefix('*')?;
+            let host = request.get_url().host_str()?.to_ascii_lowercase();
+            let label = host.strip_suffix(suffix)?;
+            return Some(label.to_string()).filter(|label| !label.contains('.'));
+        };
+        return header
+            .or_else(cookie)
+            .or_else(subdomain)
+            .map(|branch| preview_slug(&branch))
+            .filter(|slug| !slug.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_prefers_the_header_then_the_cookie_then_the_subdomain() {
+        let previews = Previews::subdomain("*.preview.example.com")
+            .cookie("preview")
+            .header("x-preview");
+        let request = Request::get("https://feature-a.preview.example.com/")
+            .with_header("cookie", "preview=feature/B")
+            .with_header("x-preview", "Feature/C");
+        assert_eq!(previews.find(&request).as_deref(), Some("feature-c"));
+        let request = Request::get("https://feature-a.preview.example.com/")
+            .with_header("cookie", "preview=feature/B");
+        assert_eq!(previews.find(&request).as_deref(), Some("feature-b"));
+        let request = Request::get("https://feature-a.preview.example.com/");
+        assert_eq!(previews.find(&request).as_deref(), Some("feature-a"));
+        let request = Request::get("https://www.example.com/");
+        assert_eq!(previews.find(&request), None);
+    }
+
+    #[test]
+    fn vary_names_the_header_and_cookie_which_pick_the_preview() {
+        let mut response = Response::new();
+        Previews::subdomain("*.preview.example.com")
+            .cookie("preview")
+            .header("x-preview")
+            .vary(&mut response);
+        assert_eq!(response.get_header_str("vary"), Some("x-preview, Cookie"));
+
+        let mut response = Response::new();
+        Previews::subdomain("*.preview.example.com").vary(&mut response);
+        assert!(!response.contains_header("vary"));
+    }
+}
@@ -0,0 +1,115 @@
+use fastly::Response;
+use std::collections::BTreeMap;
+
+/// Security headers added to every response by
+/// [`FileServer::security_headers`](crate::FileServer::security_headers).
+///
+/// The default preset is:
+///
+/// ```text
+/// Content-Security-Policy: default-src 'self'; object-src 'none'; base-uri 'self'; frame-ancestors 'self'
+/// Referrer-Policy: strict-origin-when-cross-origin
+/// Strict-Transport-Security: max-age=63072000; includeSubDomains
+/// X-Content-Type-Options: nosniff
+/// ```
+///
+/// A header which the response already has, such as one set for the path by
+/// the `_headers` file, is never replaced.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SecurityHeaders {
+    headers: BTreeMap<String, String>,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        return SecurityHeaders::none()
+            .header(
+                "content-security-policy",
+                "default-src 'self'; object-src 'none'; base-uri 'self'; frame-ancestors 'self'",
+            )
+            .header("referrer-policy", "strict-origin-when-cross-origin")
+            .header(
+                "strict-transport-security",
+                "max-age=63072000; includeSubDomains",
+            )
+            .header("x-content-type-options", "nosniff");
+    }
+}
+
+impl SecurityHeaders {
+    /// An empty preset, to build up from with [`SecurityHeaders::header`].
+    pub fn none() -> Self {
+        return SecurityHeaders {
+            headers: BTreeMap::new(),
+        };
+    }
+
+    /// Adds `name` to the preset, replacing its value if it is already set.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers
+            .insert(name.to_ascii_lowercase(), value.to_string());
+        return self;
+    }
+
+    /// Removes `name` from the preset.
+    pub fn without(mut self, name: &str) -> Self {
+        self.headers.remove(&name.to_ascii_lowercase());
+        return self;
+    }
+
+    /// Adds each header in the preset which `response` does not already have.
+    pub(crate) fn apply(&self, response: &mut Response) {
+        for (name, value) in &self.headers {
+            if !response.contains_header(name.as_str()) {
+                response.set_header(name.as_str(), value.as_str());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_adds_the_preset_without_replacing_existing_headers() {
+        let mut response = Response::new().with_header("Content-Security-Policy", "default-src *");
+        SecurityHeaders::default().apply(&mut response);
+        assert_eq!(
+            response.get_header_str("content-security-policy"),
+            Some("default-src *")
+        );
+        assert_eq!(
+            response.get_header_str("referrer-policy"),
+            Some("strict-origin-when-cross-origin")
+        );
+        assert_eq!(
+            response.get_header_str("strict-transport-security"),
+            Some("max-age=63072000; includeSubDomains")
+        );
+        assert_eq!(
+            response.get_header_str("x-content-type-options"),
+            Some("nosniff")
+        );
+    }
+
+    #[test]
+    fn header_and_without_change_the_preset() {
+        let mut response = Response::new();
+        SecurityHeaders::default()
+            .header("Referrer-Policy", "no-referrer")
+            .header("X-Frame-Options", "DENY")
+            .without("Strict-Transport-Security")
+            .apply(&mut response);
+        assert_eq!(
+            response.get_header_str("referrer-policy"),
+            Some("no-referrer")
+        );
+        assert_eq!(response.get_header_str("x-frame-options"), Some("DENY"));
+        assert!(!response.contains_header("strict-transport-security"));
+
+        let mut response = Response::new();
+        SecurityHeaders::none().apply(&mut response);
+        assert_eq!(response.get_header_names().count(), 0);
+    }
+}
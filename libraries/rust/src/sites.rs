@@ -0,0 +1,107 @@
+use crate::layers::Layers;
+use compute_file_server_metadata::{Site, SITES_KEY};
+use fastly::{ConfigStore, Error, Request};
+use std::collections::HashMap;
+
+/// Where the table which maps each hostname to a site is read from.
+///
+/// Hostnames may start with a `*.` wildcard, such as `*.example.com`, and
+/// `*` on its own is the default for hostnames which match no other entry.
+/// The most specific entry wins.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sites {
+    /// The table which `register-site` stores alongside the files.
+    Store,
+    /// The Config Store named `name`, whose keys are hostnames and whose
+    /// values are sites such as `{"prefix": "/docs"}` or
+    /// `{"store": "docs"}`.
+    ConfigStore { name: String },
+}
+
+impl Sites {
+    pub fn store() -> Self {
+        return Sites::Store;
+    }
+
+    pub fn config_store(name: &str) -> Self {
+        return Sites::ConfigStore {
+            name: name.to_string(),
+        };
+    }
+
+    /// Returns the site for the host of `request`, or `None` if neither its
+    /// hostname nor a default is in the table.
+    pub(crate) fn find(&self, layers: &Layers, request: &Request) -> Result<Option<Site>, Error> {
+        let host = match hostname(request) {
+            Some(host) => host,
+            None => return Ok(None),
+        };
+        match self {
+            Sites::Store => {
+                let table: HashMap<String, Site> = match layers.lookup(SITES_KEY)? {
+                    Some(table) => serde_json::from_str(&table.into_string())?,
+                    None => return Ok(None),
+                };
+                return Ok(candidates(&host)
+                    .into_iter()
+                    .find_map(|candidate| table.get(&candidate).cloned()));
+            }
+            Sites::ConfigStore { name } => {
+                let store = ConfigStore::try_open(name)?;
+                for candidate in candidates(&host) {
+                    if let Some(site) = store.try_get(&candidate)? {
+                        return Ok(Some(serde_json::from_str(&site)?));
+                    }
+                }
+                return Ok(None);
+            }
+        }
+    }
+}
+
+/// Returns the lowercased hostname of `request`, without its port.
+fn hostname(request: &Request) -> Option<String> {
+    return request
+        .get_url()
+        .host_str()
+        .map(|host| host.to_ascii_lowercase());
+}
+
+/// Returns the entries which could match `host`, most specific first, so
+/// `a.example.com` is followed by `*.example.com`, `*.com` and `*`.
+fn candidates(host: &str) -> Vec<String> {
+    let mut candidates = vec![host.to_string()];
+    let mut rest = host;
+    while let Some((_, parent)) = rest.split_once('.') {
+        candidates.push(format!("*.{}", parent));
+        rest = parent;
+    }
+    candidates.push("*".to_string());
+    return candidates;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn candidates_go_from_the_exact_host_to_wildcards_to_the_default() {
+        assert_eq!(
+            candidates("a.docs.example.com"),
+            [
+                "a.docs.example.com",
+                "*.docs.example.com",
+                "*.example.com",
+                "*.com",
+                "*"
+            ]
+        );
+        assert_eq!(candidates("localhost"), ["localhost", "*"]);
+    }
+
+    #[test]
+    fn hostname_strips_the_port_and_case() {
+        let request = Request::get("https://Docs.Example.com:8443/guide/");
+        assert_eq!(hostname(&request).as_deref(), Some("docs.example.com"));
+    }
+}
@@ -487,7 +487,7 @@ async fn range(url: &str, method: &Method) {
     assert_eq!(res.status(), 206);
     match method {
         Method::HEAD => assert_eq!(res.bytes().await.unwrap(), bytes::Bytes::from(&b""[..])),
-        Method::GET => assert_eq!(res.bytes().await.unwrap(), bytes::Bytes::from(&b"\n--3d6b6a416f9b5\nContent-Type: text/html\nContent-Range: bytes 1-10/679\n\n!doctype \n--3d6b6a416f9b5\nContent-Type: text/html\nContent-Range: bytes 20-30/679\n\nl>\n<head>\n\n--3d6b6a416f9b5\n"[..])),
+        Method::GET => assert_eq!(res.bytes().await.unwrap(), bytes::Bytes::from(&b"\n--3d6b6a416f9b5\nContent-Type: text/html\nContent-Range: bytes 1-10/679\nContent-Digest: sha-256=:EAW56GnZC46NORhpF1C7QdppmmEO/E6kNwnkJRJLDls=:\n\n!doctype \n--3d6b6a416f9b5\nContent-Type: text/html\nContent-Range: bytes 20-30/679\nContent-Digest: sha-256=:1TvPubuYhSnC8kz3iyVZtOQeX4spnTLQxIvd81N7g34=:\n\nl>\n<head>\n\n--3d6b6a416f9b5\n"[..])),
         Method::PUT => unimplemented!(),
         Method::POST => unimplemented!(),
         Method::DELETE => unimplemented!(),
@@ -578,7 +578,7 @@ async fn if_range(url: &str, method: &Method, expected_status: u16) {
         .unwrap();
     assert_eq!(res.status(), 206);
     if method == &Method::GET {
-        assert_eq!(res.bytes().await.unwrap(), bytes::Bytes::from(&b"\n--3d6b6a416f9b5\nContent-Type: text/html\nContent-Range: bytes 1-10/679\n\n!doctype \n--3d6b6a416f9b5\nContent-Type: text/html\nContent-Range: bytes 20-30/679\n\nl>\n<head>\n\n--3d6b6a416f9b5\n"[..]));
+        assert_eq!(res.bytes().await.unwrap(), bytes::Bytes::from(&b"\n--3d6b6a416f9b5\nContent-Type: text/html\nContent-Range: bytes 1-10/679\nContent-Digest: sha-256=:EAW56GnZC46NORhpF1C7QdppmmEO/E6kNwnkJRJLDls=:\n\n!doctype \n--3d6b6a416f9b5\nContent-Type: text/html\nContent-Range: bytes 20-30/679\nContent-Digest: sha-256=:1TvPubuYhSnC8kz3iyVZtOQeX4spnTLQxIvd81N7g34=:\n\nl>\n<head>\n\n--3d6b6a416f9b5\n"[..]));
     } else {
         assert_eq!(res.bytes().await.unwrap(), bytes::Bytes::from(&b""[..]));
     }
@@ -599,6 +599,30 @@ async fn if_range(url: &str, method: &Method, expected_status: u16) {
     assert_eq!(res.bytes().await.unwrap(), body);
 }
 
+#[allow(dead_code)]
+async fn head_and_get_headers_match(url: &str) {
+    let client = reqwest::Client::new();
+
+    let get = get_original_response(url, &Method::GET).await;
+    let head = get_original_response(url, &Method::HEAD).await;
+    for name in ["content-length", "content-type", "etag", "last-modified", "accept-ranges", "cache-control", "repr-digest"] {
+        assert_eq!(head.headers().get(name), get.headers().get(name), "{} differs between HEAD and GET", name);
+    }
+    let content_length = get.headers().get("content-length").unwrap().to_owned();
+    let etag = get.headers().get("etag").unwrap().to_owned();
+    let body = get.bytes().await.unwrap();
+    assert_eq!(content_length.to_str().unwrap(), body.len().to_string());
+    assert_eq!(head.bytes().await.unwrap(), bytes::Bytes::new());
+
+    let res = client.get(url)
+        .header("if-none-match", &etag)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 304);
+    assert_eq!(res.headers().get("content-length"), Some(&content_length));
+}
+
 #[tokio::test]
 async fn test_get_root_path_if_match() {
     let url = format!("{}/", get_host());
@@ -695,3 +719,10 @@ async fn test_head_root_path_if_range() {
     let url = format!("{}/", host);
     if_range(&url, &Method::HEAD, 206).await;
 }
+
+#[tokio::test]
+async fn test_root_path_head_and_get_headers_match() {
+    let host = get_host();
+    let url = format!("{}/", host);
+    head_and_get_headers_match(&url).await;
+}